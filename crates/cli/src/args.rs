@@ -0,0 +1,136 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::{Parser, ValueEnum};
+use proxy_lib::{DisplayFilter, PacketSide, PacketState, STD_PROTOCOL_VERSION};
+use tracing::Level;
+
+#[derive(Parser)]
+#[command(
+    about = "Minecraft packet inspecting proxy",
+    after_help = "Packets can be sent into live sessions by typing on stdin:\n  \
+                  inject <session> <c2s|s2c> <packet name or id> [hex body]"
+)]
+pub struct Args {
+    /// Address to accept client connections on
+    #[arg(short, long, value_name = "ADDR", default_value = "0.0.0.0:25566")]
+    pub listen: SocketAddr,
+
+    /// Address of the server to relay to
+    #[arg(short, long, value_name = "ADDR", default_value = "127.0.0.1:25565")]
+    pub server: SocketAddr,
+
+    /// JSON file naming extra packets, in the format of the bundled packet tables
+    #[arg(long, value_name = "FILE")]
+    pub definitions: Option<PathBuf>,
+
+    /// Protocol version the packet definitions apply to
+    #[arg(long, value_name = "VERSION", default_value_t = STD_PROTOCOL_VERSION)]
+    pub definitions_protocol: i32,
+
+    /// Decrypt online-mode logins by terminating encryption at the proxy. The server must not
+    /// verify logins with Mojang.
+    #[arg(long)]
+    pub online_mode: bool,
+
+    /// Most verbose log messages to show on stderr
+    #[arg(long, value_name = "LEVEL", default_value = "info")]
+    pub log_level: Level,
+
+    /// Don't print packets, only log warnings and errors
+    #[arg(short, long, conflicts_with = "log_level")]
+    pub quiet: bool,
+
+    /// Only print packets whose name matches, `*` matches any run of characters
+    #[arg(long, value_name = "NAME")]
+    pub include: Vec<String>,
+
+    /// Don't print packets whose name matches, `*` matches any run of characters
+    #[arg(long, value_name = "NAME")]
+    pub exclude: Vec<String>,
+
+    /// Only print packets travelling in this direction
+    #[arg(long, value_enum)]
+    pub side: Option<Side>,
+
+    /// Only print packets sent in these connection states
+    #[arg(long, value_enum)]
+    pub state: Vec<State>,
+
+    /// Only print packets passing this display filter, like `name ~ "Move" && len > 100`
+    #[arg(long, value_name = "EXPR")]
+    pub filter: Option<DisplayFilter>,
+
+    /// How to print packets on stdout
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub format: OutputFormat,
+
+    /// Print the decoded fields below each packet in text output
+    #[arg(short, long)]
+    pub decode: bool,
+
+    /// How packet bodies are written in JSON output
+    #[arg(long, value_enum, default_value_t = BodyEncoding::Base64)]
+    pub body_encoding: BodyEncoding,
+
+    /// Write every session to this capture file, regardless of the filters
+    #[arg(short, long, value_name = "FILE")]
+    pub write: Option<PathBuf>,
+
+    /// Stop after printing this many packets
+    #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u64).range(1..))]
+    pub max_packets: Option<u64>,
+
+    /// Stop after running for this many seconds
+    #[arg(long, value_name = "SECS")]
+    pub duration: Option<u64>,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum Side {
+    /// Client to server
+    C2s,
+    /// Server to client
+    S2c,
+}
+
+impl From<Side> for PacketSide {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::C2s => PacketSide::Serverbound,
+            Side::S2c => PacketSide::Clientbound,
+        }
+    }
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+pub enum State {
+    Handshaking,
+    Status,
+    Login,
+    Play,
+}
+
+impl From<State> for PacketState {
+    fn from(state: State) -> Self {
+        match state {
+            State::Handshaking => PacketState::Handshaking,
+            State::Status => PacketState::Status,
+            State::Login => PacketState::Login,
+            State::Play => PacketState::Play,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// One human readable line per packet
+    Text,
+    /// One JSON object per line and packet
+    Jsonl,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, ValueEnum)]
+pub enum BodyEncoding {
+    Base64,
+    Hex,
+}
@@ -0,0 +1,167 @@
+use proxy_lib::{DisplayFilter, Packet, PacketSide, PacketState};
+
+use crate::args::Args;
+
+/// Decides which packets get printed.
+pub struct PacketFilter {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    side: Option<PacketSide>,
+    states: Vec<PacketState>,
+    expression: Option<DisplayFilter>,
+}
+
+impl PacketFilter {
+    pub fn from_args(args: &Args) -> Self {
+        Self {
+            include: args.include.clone(),
+            exclude: args.exclude.clone(),
+            side: args.side.map(Into::into),
+            states: args.state.iter().copied().map(Into::into).collect(),
+            expression: args.filter.clone(),
+        }
+    }
+
+    pub fn matches(&self, packet: &Packet) -> bool {
+        if self.side.map_or(false, |side| side != packet.side) {
+            return false;
+        }
+
+        if !self.states.is_empty() && !self.states.contains(&packet.state) {
+            return false;
+        }
+
+        if !self.include.is_empty() && !self.include.iter().any(|p| glob_match(p, packet.name)) {
+            return false;
+        }
+
+        if self.exclude.iter().any(|p| glob_match(p, packet.name)) {
+            return false;
+        }
+
+        // last, it may have to decode the packet
+        self.expression
+            .as_ref()
+            .map_or(true, |expression| expression.matches(packet))
+    }
+}
+
+/// Case insensitive, `*` matches any run of characters.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    let name = name.to_ascii_lowercase();
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts = parts.collect::<Vec<_>>();
+    let Some(last) = parts.pop() else {
+        // no wildcard at all
+        return rest.is_empty();
+    };
+
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(name: &'static str) -> Packet {
+        Packet {
+            side: PacketSide::Clientbound,
+            state: PacketState::Play,
+            id: 0,
+            timestamp: None,
+            name,
+            data: None,
+            session: None,
+            schema: None,
+        }
+    }
+
+    fn filter(include: &[&str], exclude: &[&str]) -> PacketFilter {
+        PacketFilter {
+            include: include.iter().map(|p| p.to_string()).collect(),
+            exclude: exclude.iter().map(|p| p.to_string()).collect(),
+            side: None,
+            states: Vec::new(),
+            expression: None,
+        }
+    }
+
+    #[test]
+    fn glob_without_wildcard_is_exact() {
+        assert!(glob_match("KeepAliveS2c", "KeepAliveS2c"));
+        assert!(!glob_match("KeepAlive", "KeepAliveS2c"));
+        assert!(!glob_match("KeepAliveS2c", "KeepAlive"));
+    }
+
+    #[test]
+    fn glob_leading_and_trailing_wildcards() {
+        assert!(glob_match("*S2c", "KeepAliveS2c"));
+        assert!(!glob_match("*S2c", "KeepAliveC2s"));
+        assert!(glob_match("KeepAlive*", "KeepAliveC2s"));
+        assert!(!glob_match("KeepAlive*", "ChatMessageC2s"));
+        assert!(glob_match("*Alive*", "KeepAliveC2s"));
+        assert!(glob_match("*", ""));
+    }
+
+    #[test]
+    fn glob_segments_dont_overlap() {
+        assert!(glob_match("a*a", "aa"));
+        assert!(glob_match("a*a", "aba"));
+        assert!(!glob_match("a*a", "a"));
+        assert!(glob_match("*a*a*", "xaxax"));
+        assert!(!glob_match("*a*a*", "xax"));
+        assert!(!glob_match("*ab*b", "ab"));
+    }
+
+    #[test]
+    fn glob_ignores_case() {
+        assert!(glob_match("keepalive*", "KeepAliveS2c"));
+        assert!(glob_match("*S2C", "KeepAliveS2c"));
+    }
+
+    #[test]
+    fn include_and_exclude() {
+        let f = filter(&["*S2c"], &["KeepAlive*"]);
+
+        assert!(f.matches(&packet("ChunkDataS2c")));
+        assert!(!f.matches(&packet("KeepAliveS2c")));
+        assert!(!f.matches(&packet("ChatMessageC2s")));
+    }
+
+    #[test]
+    fn no_include_matches_everything_not_excluded() {
+        let f = filter(&[], &["keepalive*"]);
+
+        assert!(f.matches(&packet("ChatMessageC2s")));
+        assert!(!f.matches(&packet("KeepAliveS2c")));
+        assert!(filter(&[], &[]).matches(&packet("KeepAliveS2c")));
+    }
+
+    #[test]
+    fn side_and_state() {
+        let mut f = filter(&[], &[]);
+        f.side = Some(PacketSide::Serverbound);
+        assert!(!f.matches(&packet("ChunkDataS2c")));
+
+        f.side = Some(PacketSide::Clientbound);
+        f.states = vec![PacketState::Login];
+        assert!(!f.matches(&packet("ChunkDataS2c")));
+
+        f.states.push(PacketState::Play);
+        assert!(f.matches(&packet("ChunkDataS2c")));
+    }
+}
@@ -1,49 +1,224 @@
+mod args;
+mod filter;
+mod output;
+
+use std::fs::File;
+use std::io::{BufRead, BufWriter};
+use std::time::Duration;
+
+use anyhow::{bail, Context};
+use clap::Parser;
+use proxy_lib::parse_hex;
+use proxy_lib::CaptureWriter;
+use proxy_lib::Injector;
 use proxy_lib::Packet;
+use proxy_lib::PacketDefinitions;
+use proxy_lib::PacketSide;
 use proxy_lib::Proxy;
+use proxy_lib::ProxyEvent;
+use proxy_lib::SessionId;
+use proxy_lib::ShutdownMode;
+use proxy_lib::STD_PACKETS;
 use tracing::Level;
 
+use crate::args::{Args, OutputFormat};
+use crate::filter::PacketFilter;
+
+const INJECT_USAGE: &str = "usage: inject <session> <c2s|s2c> <packet name or id> [hex body]";
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+
     tracing_subscriber::fmt()
-        .with_max_level(Level::TRACE)
+        .with_max_level(if args.quiet {
+            Level::WARN
+        } else {
+            args.log_level
+        })
+        .with_writer(std::io::stderr)
         .init();
 
-    let proxy = Proxy::new("0.0.0.0:25566".parse()?, "127.0.0.1:25565".parse()?);
+    let mut proxy = Proxy::new(args.listen, args.server);
+    if args.online_mode {
+        proxy = proxy.with_encryption()?;
+    }
+
+    // packets the inject command can name
+    let mut packets = STD_PACKETS.to_vec();
+    if let Some(path) = &args.definitions {
+        let definitions = PacketDefinitions::load(path, args.definitions_protocol)?;
+        tracing::info!(
+            "Loaded {} packet definitions for protocol {}",
+            definitions.packets.len(),
+            definitions.protocol_version
+        );
+        proxy = proxy.with_packet_definitions(&definitions);
+        packets = definitions.overlay(&packets);
+    }
+
     let receiver = proxy.subscribe();
+    let shutdown = proxy.shutdown_handle();
+    let injector = proxy.injector();
 
-    tokio::spawn(async move {
-        proxy.run().await?;
+    // a plain thread, tokio's stdin would keep the runtime from shutting down
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
 
-        Ok::<(), anyhow::Error>(())
+            if let Err(e) = run_command(&injector, &packets, &line) {
+                tracing::error!("{:#}", e);
+            }
+        }
     });
 
-    // consumer
-    tokio::spawn(async move {
-        while let Ok(packet) = receiver.recv_async().await {
-            log(&packet);
+    let mut proxy_task = tokio::spawn(async move { proxy.run().await });
+
+    let mut capture = match &args.write {
+        Some(path) => Some(CaptureWriter::new(BufWriter::new(File::create(path)?))?),
+        None => None,
+    };
+
+    let filter = PacketFilter::from_args(&args);
+    let print = !args.quiet;
+    let format = args.format;
+    let body_encoding = args.body_encoding;
+    let decode = args.decode;
+    let max_packets = args.max_packets;
+    let consumer_shutdown = shutdown.clone();
+
+    // consumer, finishes once the proxy is dropped
+    let consumer = tokio::spawn(async move {
+        let mut printed = 0;
+
+        let res = async {
+            while let Ok(event) = receiver.recv_async().await {
+                if let Some(writer) = &mut capture {
+                    writer.write_event(&event)?;
+                }
+
+                match event {
+                    ProxyEvent::SessionOpened(session) => tracing::info!(
+                        "Session {} opened: {} -> {}",
+                        session.id,
+                        session.client_addr,
+                        session.server_addr
+                    ),
+                    ProxyEvent::Packet(packet) => {
+                        // the proxy takes a moment to stop, packets still come in meanwhile
+                        let done = max_packets.is_some_and(|max| printed >= max);
+                        if !print || done || !filter.matches(&packet) {
+                            continue;
+                        }
+
+                        match format {
+                            OutputFormat::Text => output::print_text(&packet, decode),
+                            OutputFormat::Jsonl => output::print_jsonl(&packet, body_encoding)?,
+                        }
+
+                        printed += 1;
+                        if max_packets == Some(printed) {
+                            tracing::info!("Printed {} packets, stopping", printed);
+                            consumer_shutdown.shutdown(ShutdownMode::Force);
+                        }
+                    }
+                    ProxyEvent::SessionClosed(session, reason) => {
+                        tracing::info!("Session {} closed ({})", session.id, reason)
+                    }
+                }
+            }
+
+            if let Some(writer) = &mut capture {
+                writer.flush()?;
+            }
+
+            Ok::<(), anyhow::Error>(())
         }
+        .await;
+
+        // otherwise a failed write is only reported once the proxy stops for another reason
+        if res.is_err() {
+            consumer_shutdown.shutdown(ShutdownMode::Force);
+        }
+
+        res
     });
 
-    tokio::signal::ctrl_c().await.unwrap();
+    let deadline = async {
+        match args.duration {
+            Some(secs) => tokio::time::sleep(Duration::from_secs(secs)).await,
+            None => std::future::pending().await,
+        }
+    };
 
-    Ok(())
-}
+    let summary = tokio::select! {
+        res = &mut proxy_task => res??,
+        res = tokio::signal::ctrl_c() => {
+            res?;
+            shutdown.shutdown(ShutdownMode::Force);
+            proxy_task.await??
+        }
+        _ = deadline => {
+            tracing::info!("Ran for {} seconds, stopping", args.duration.unwrap_or_default());
+            shutdown.shutdown(ShutdownMode::Force);
+            proxy_task.await??
+        }
+    };
+    consumer.await??;
 
-fn log(packet: &Packet) {
-    tracing::debug!(
-        "{:?} -> [{:?}] 0x{:0>2X} \"{}\" {:?}",
-        packet.side,
-        packet.state,
-        packet.id,
-        packet.name,
-        truncated(format!("{:?}", packet.data), 512)
+    tracing::info!(
+        "Proxy stopped: {} sessions accepted, {} drained, {} closed",
+        summary.sessions_accepted,
+        summary.sessions_drained,
+        summary.sessions_closed
     );
+
+    Ok(())
 }
 
-fn truncated(string: String, max_len: usize) -> String {
-    if string.len() > max_len {
-        format!("{}...", &string[..max_len])
-    } else {
-        string
+fn run_command(injector: &Injector, packets: &[Packet], line: &str) -> anyhow::Result<()> {
+    let mut args = line.split_whitespace();
+    match args.next() {
+        None => return Ok(()),
+        Some("inject") => {}
+        Some(command) => bail!("unknown command \"{}\", {}", command, INJECT_USAGE),
     }
+
+    let (Some(session), Some(side), Some(packet)) = (args.next(), args.next(), args.next()) else {
+        bail!(INJECT_USAGE);
+    };
+
+    let session = session
+        .trim_start_matches('#')
+        .parse()
+        .map(SessionId)
+        .context("invalid session id")?;
+
+    let side = match side {
+        "c2s" => PacketSide::Serverbound,
+        "s2c" => PacketSide::Clientbound,
+        _ => bail!("direction must be c2s or s2c"),
+    };
+
+    let parsed_id = match packet.strip_prefix("0x") {
+        Some(hex) => i32::from_str_radix(hex, 16).ok(),
+        None => packet.parse().ok(),
+    };
+    let id = match parsed_id {
+        Some(id) => id,
+        None => packets
+            .iter()
+            .find(|p| p.side == side && p.name.eq_ignore_ascii_case(packet))
+            .map(|p| p.id)
+            .with_context(|| format!("unknown packet \"{}\"", packet))?,
+    };
+
+    let body = parse_hex(&args.collect::<String>()).context("body is not valid hex")?;
+
+    injector.inject(session, side, id, &body)?;
+    tracing::info!("Injected 0x{:0>2X} into session {}", id, session);
+
+    Ok(())
 }
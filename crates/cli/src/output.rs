@@ -0,0 +1,94 @@
+use std::io::Write;
+
+use base64::Engine;
+use proxy_lib::{decode_fields, packet_to_string, Field, Packet};
+use time::{format_description::FormatItem, macros::format_description};
+
+use crate::args::BodyEncoding;
+
+/// RFC 3339 with all nine subsecond digits, so lines sort and diff consistently.
+const TIMESTAMP_FORMAT: &[FormatItem] = format_description!(
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:9][offset_hour sign:mandatory]:[offset_minute]"
+);
+
+#[derive(serde::Serialize)]
+struct JsonPacket<'a> {
+    session: Option<u64>,
+    side: String,
+    state: String,
+    id: i32,
+    name: &'a str,
+    timestamp: Option<String>,
+    length: usize,
+    body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    decoded: Option<Field>,
+}
+
+pub fn print_text(packet: &Packet, decode: bool) {
+    let session = match &packet.session {
+        Some(session) => session.id.to_string(),
+        None => "-".to_string(),
+    };
+    let timestamp = match packet.timestamp {
+        Some(timestamp) => timestamp.to_string(),
+        None => "-".to_string(),
+    };
+
+    println!(
+        "{} {} {:?} -> [{:?}] 0x{:0>2X} \"{}\" {:?}",
+        timestamp,
+        session,
+        packet.side,
+        packet.state,
+        packet.id,
+        packet.name,
+        truncated(format!("{:?}", packet.data), 512)
+    );
+
+    if decode {
+        for line in packet_to_string(packet).lines() {
+            println!("    {}", line);
+        }
+    }
+}
+
+pub fn print_jsonl(packet: &Packet, encoding: BodyEncoding) -> anyhow::Result<()> {
+    let data = packet.data.as_deref().unwrap_or_default();
+
+    let body = match encoding {
+        BodyEncoding::Base64 => base64::engine::general_purpose::STANDARD.encode(data),
+        BodyEncoding::Hex => data.iter().map(|b| format!("{:02x}", b)).collect(),
+    };
+
+    let timestamp = match packet.timestamp {
+        Some(timestamp) => Some(timestamp.format(TIMESTAMP_FORMAT)?),
+        None => None,
+    };
+
+    let json = JsonPacket {
+        session: packet.session.as_ref().map(|s| s.id.0),
+        side: format!("{:?}", packet.side),
+        state: format!("{:?}", packet.state),
+        id: packet.id,
+        name: packet.name,
+        timestamp,
+        length: data.len(),
+        body,
+        decoded: decode_fields(packet).and_then(Result::ok),
+    };
+
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, &json)?;
+    writeln!(stdout)?;
+
+    Ok(())
+}
+
+fn truncated(string: String, max_len: usize) -> String {
+    if string.len() > max_len {
+        format!("{}...", &string[..max_len])
+    } else {
+        string
+    }
+}
@@ -1,14 +1,26 @@
-use std::sync::{Arc, RwLock};
+use std::{
+    fs::File,
+    io::BufReader,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, RwLock},
+};
 
 use egui_dock::{DockArea, NodeIndex, Style, Tree};
-use proxy_lib::Proxy;
+use proxy_lib::{CaptureReader, Proxy, ProxyEvent, ShutdownMode};
 use tokio::task::JoinHandle;
 
-use crate::shared_state::{Event, SharedState};
+use crate::{
+    breakpoints::BreakpointInterceptor,
+    shared_state::{Event, SharedState},
+};
 
+mod breakpoints;
 mod connection;
+mod field_editor;
 mod filter;
 mod hex_viewer;
+mod inject;
 mod packet_list;
 mod text_viewer;
 
@@ -65,7 +77,15 @@ impl GuiApp {
             vec![Box::new(packet_list::PacketList::new())],
         );
 
-        let [_, _] = tree.split_below(a, 0.25, vec![Box::new(filter::Filter::new())]);
+        let [_, _] = tree.split_below(
+            a,
+            0.25,
+            vec![
+                Box::new(filter::Filter::new()),
+                Box::new(breakpoints::Breakpoints::new()),
+                Box::new(inject::Inject::new()),
+            ],
+        );
         let [_, _] = tree.split_below(
             b,
             0.5,
@@ -126,7 +146,7 @@ impl eframe::App for GuiApp {
 // This function is getting waaaay too complcated and messy
 fn handle_events(state: Arc<RwLock<SharedState>>) {
     tokio::spawn(async move {
-        let mut proxy_thread: Option<JoinHandle<_>> = None;
+        let mut replay: Option<JoinHandle<()>> = None;
 
         let receiver = state.write().unwrap().receiver.take().unwrap();
         while let Ok(event) = receiver.recv_async().await {
@@ -137,27 +157,52 @@ fn handle_events(state: Arc<RwLock<SharedState>>) {
                         continue;
                     }
 
-                    let listener_addr = w_state.listener_addr.clone();
-                    let server_addr = w_state.server_addr.clone();
-                    let state = state.clone();
-
-                    proxy_thread = Some(tokio::spawn(async move {
-                        let proxy = Proxy::new(listener_addr.parse()?, server_addr.parse()?);
-                        let receiver = proxy.subscribe();
-
-                        tokio::spawn(async move {
-                            proxy.run().await?;
-                            Ok::<(), anyhow::Error>(())
-                        });
-
-                        while let Ok(packet) = receiver.recv_async().await {
-                            let state = state.read().unwrap();
-                            state.packets.write().unwrap().push(packet);
-                            state.send_event(Event::PacketReceived);
+                    let (Ok(listener_addr), Ok(server_addr)) = (
+                        w_state.listener_addr.parse::<SocketAddr>(),
+                        w_state.server_addr.parse::<SocketAddr>(),
+                    ) else {
+                        tracing::error!("Invalid listener or server address");
+                        continue;
+                    };
+
+                    let mut proxy = Proxy::new(listener_addr, server_addr).with_interceptor(
+                        Arc::new(BreakpointInterceptor::new(
+                            w_state.breakpoints.clone(),
+                            w_state.ctx.clone(),
+                        )),
+                    );
+                    if let Some(definitions) = &w_state.definitions {
+                        proxy = proxy.with_packet_definitions(definitions);
+                    }
+                    if w_state.online_mode {
+                        proxy = match proxy.with_encryption() {
+                            Ok(proxy) => proxy,
+                            Err(e) => {
+                                tracing::error!("Failed to set up encryption: {:?}", e);
+                                continue;
+                            }
+                        };
+                    }
+                    let receiver = proxy.subscribe();
+                    w_state.shutdown = Some(proxy.shutdown_handle());
+                    w_state.injector = Some(proxy.injector());
+
+                    let run_state = state.clone();
+                    tokio::spawn(async move {
+                        match proxy.run().await {
+                            Ok(summary) => tracing::info!("Proxy stopped: {:?}", summary),
+                            Err(e) => {
+                                tracing::error!("Proxy failed: {:?}", e);
+                                let mut state = run_state.write().unwrap();
+                                state.shutdown = None;
+                                state.injector = None;
+                                state.is_listening = false;
+                            }
                         }
+                    });
 
-                        Ok::<(), anyhow::Error>(())
-                    }));
+                    // finishes once the proxy is dropped
+                    spawn_consumer(state.clone(), receiver);
 
                     w_state.is_listening = true;
                 }
@@ -167,12 +212,54 @@ fn handle_events(state: Arc<RwLock<SharedState>>) {
                         continue;
                     }
 
-                    if let Some(proxy_thread) = proxy_thread.take() {
-                        proxy_thread.abort();
+                    if let Some(shutdown) = state.shutdown.take() {
+                        shutdown.shutdown(ShutdownMode::Force);
                     }
 
+                    state.injector = None;
                     state.is_listening = false;
                 }
+                Event::StartReplay(path) => {
+                    let mut w_state = state.write().unwrap();
+                    if w_state.is_listening || w_state.is_replaying {
+                        continue;
+                    }
+
+                    let events = match read_capture(&path) {
+                        Ok(events) => events,
+                        Err(e) => {
+                            tracing::error!("Failed to open capture: {:?}", e);
+                            continue;
+                        }
+                    };
+
+                    w_state.clear_capture();
+
+                    let (sender, receiver) = flume::unbounded();
+                    let speed = w_state.replay_speed as f64;
+                    let replay_state = state.clone();
+                    replay = Some(tokio::spawn(async move {
+                        proxy_lib::replay_timed(events, sender, speed).await;
+                        replay_state.write().unwrap().is_replaying = false;
+                    }));
+
+                    // finishes once the replay is done or stopped
+                    spawn_consumer(state.clone(), receiver);
+
+                    w_state.is_replaying = true;
+                }
+                Event::StopReplay => {
+                    let mut state = state.write().unwrap();
+                    if !state.is_replaying {
+                        continue;
+                    }
+
+                    if let Some(replay) = replay.take() {
+                        replay.abort();
+                    }
+
+                    state.is_replaying = false;
+                }
                 Event::PacketReceived => {
                     // Refresh UI
                     if let Some(ctx) = &state.read().unwrap().ctx {
@@ -183,3 +270,18 @@ fn handle_events(state: Arc<RwLock<SharedState>>) {
         }
     });
 }
+
+/// Feeds proxy events into the shared state until the sending side is gone.
+fn spawn_consumer(state: Arc<RwLock<SharedState>>, receiver: flume::Receiver<ProxyEvent>) {
+    tokio::spawn(async move {
+        while let Ok(event) = receiver.recv_async().await {
+            let state = state.read().unwrap();
+            state.apply_proxy_event(event);
+            state.send_event(Event::PacketReceived);
+        }
+    });
+}
+
+fn read_capture(path: &Path) -> anyhow::Result<Vec<ProxyEvent>> {
+    CaptureReader::new(BufReader::new(File::open(path)?))?.collect()
+}
@@ -0,0 +1,350 @@
+use std::ops::Range;
+
+use bytes::BytesMut;
+use proxy_lib::{
+    decode_fields, packet_to_string, parse_hex, Field, Packet, PacketDefinitions, PacketSide,
+    Verdict,
+};
+
+use crate::{
+    breakpoints::{Breakpoint, PausedPacket},
+    shared_state::apply_definitions,
+};
+
+use super::{
+    field_editor::FieldKind, hex_viewer::format_hex, text_viewer::code_view_ui, SharedState, Tab,
+    View,
+};
+
+/// A top level field of the selected packet, written back into the body when it is edited.
+struct BodyField {
+    name: String,
+    ty: String,
+    kind: FieldKind,
+    value: String,
+    /// Where the field is in `Breakpoints::body`
+    range: Range<usize>,
+    /// Why `value` couldn't be encoded
+    error: Option<String>,
+}
+
+impl BodyField {
+    fn read(field: &Field, body: &[u8]) -> Option<Self> {
+        let range = field.range()?;
+        let bytes = body.get(range.clone())?;
+
+        // fields the editor can't type in are edited as their encoded bytes
+        let kind = FieldKind::of(&field.ty);
+        let (kind, value) = match kind.decode(bytes) {
+            Some(value) => (kind, value),
+            None => (FieldKind::Raw, FieldKind::Raw.decode(bytes)?),
+        };
+
+        Some(Self {
+            name: field.name.clone(),
+            ty: field.ty.clone(),
+            kind,
+            value,
+            range,
+            error: None,
+        })
+    }
+}
+
+pub struct Breakpoints {
+    selected: Option<usize>,
+    hex: String,
+    decoded: String,
+    /// The body `fields` were read from, `hex` as bytes while it is valid
+    body: Vec<u8>,
+    /// Empty if the packet can't be decoded
+    fields: Vec<BodyField>,
+}
+
+impl Tab for Breakpoints {
+    fn new() -> Self {
+        Self {
+            selected: None,
+            hex: String::new(),
+            decoded: String::new(),
+            body: Vec::new(),
+            fields: Vec::new(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Breakpoints"
+    }
+}
+
+impl View for Breakpoints {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut SharedState) {
+        let breakpoints = state.breakpoints.clone();
+        let definitions = state.definitions.clone();
+        let definitions = definitions.as_deref();
+
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                draw_rules(ui, &mut breakpoints.rules.write().unwrap());
+                ui.separator();
+
+                let mut paused = breakpoints.paused.lock().unwrap();
+
+                // sessions that closed while paused don't need an answer anymore
+                let count = paused.len();
+                paused.retain(|p| !p.is_abandoned());
+                if paused.len() != count {
+                    self.selected = None;
+                }
+
+                if self.selected.is_none() && !paused.is_empty() {
+                    self.select(&paused, 0, definitions);
+                }
+
+                ui.horizontal(|ui| {
+                    ui.heading(format!("Paused ({})", paused.len()));
+                    if ui
+                        .add_enabled(!paused.is_empty(), egui::Button::new("Forward all"))
+                        .clicked()
+                    {
+                        for packet in paused.drain(..) {
+                            packet.resolve(Verdict::Pass);
+                        }
+                        self.selected = None;
+                    }
+                });
+
+                let mut clicked = None;
+                for (i, packet) in paused.iter().enumerate() {
+                    let label = format!(
+                        "{} {} [{:?}] 0x{:02X} {}",
+                        packet.session.id,
+                        direction(packet.side),
+                        packet.state,
+                        packet.id,
+                        packet.name
+                    );
+                    if ui
+                        .selectable_label(self.selected == Some(i), label)
+                        .clicked()
+                    {
+                        clicked = Some(i);
+                    }
+                }
+                if let Some(i) = clicked {
+                    self.select(&paused, i, definitions);
+                }
+
+                let Some(index) = self.selected else {
+                    return;
+                };
+
+                ui.separator();
+                if let Some(verdict) = self.draw_editor(ui, &paused[index], definitions) {
+                    paused.remove(index).resolve(verdict);
+                    self.selected = None;
+                }
+            });
+    }
+}
+
+impl Breakpoints {
+    fn select(
+        &mut self,
+        paused: &[PausedPacket],
+        index: usize,
+        definitions: Option<&PacketDefinitions>,
+    ) {
+        self.selected = Some(index);
+        self.hex = format_hex(&paused[index].body);
+        self.read_hex(&paused[index], definitions);
+    }
+
+    /// Returns the verdict once the user decided on one.
+    fn draw_editor(
+        &mut self,
+        ui: &mut egui::Ui,
+        packet: &PausedPacket,
+        definitions: Option<&PacketDefinitions>,
+    ) -> Option<Verdict> {
+        let mut verdict = None;
+        let edited = parse_hex(&self.hex).filter(|_| self.fields.iter().all(|f| f.error.is_none()));
+
+        ui.horizontal(|ui| {
+            if ui.button("Forward").clicked() {
+                verdict = Some(Verdict::Pass);
+            }
+            if ui
+                .add_enabled(edited.is_some(), egui::Button::new("Forward modified"))
+                .on_disabled_hover_text("The body is not valid hex or a field is invalid")
+                .clicked()
+            {
+                if let Some(body) = &edited {
+                    verdict = Some(Verdict::Rewrite(BytesMut::from(&body[..])));
+                }
+            }
+            if ui.button("Drop").clicked() {
+                verdict = Some(Verdict::Drop);
+            }
+        });
+
+        if !self.fields.is_empty() {
+            ui.label("Fields");
+
+            let mut changed = None;
+            egui::Grid::new("breakpoint_fields").show(ui, |ui| {
+                for (i, field) in self.fields.iter_mut().enumerate() {
+                    ui.label(&field.name);
+                    ui.weak(&field.ty);
+                    if field.kind.edit(ui, &mut field.value).changed() {
+                        changed = Some(i);
+                    }
+                    if let Some(error) = &field.error {
+                        ui.colored_label(ui.visuals().error_fg_color, error);
+                    }
+                    ui.end_row();
+                }
+            });
+
+            if let Some(i) = changed {
+                self.write_field(i, packet, definitions);
+            }
+        }
+
+        ui.label("Body");
+        let response = ui.add(
+            egui::TextEdit::multiline(&mut self.hex)
+                .font(egui::TextStyle::Monospace)
+                .code_editor()
+                .desired_width(ui.available_width())
+                .desired_rows(8),
+        );
+        if response.changed() {
+            self.read_hex(packet, definitions);
+        }
+
+        ui.label("Decoded");
+        code_view_ui(ui, &self.decoded);
+
+        verdict
+    }
+
+    /// Decodes the body typed in as hex, and reads the fields from it.
+    fn read_hex(&mut self, packet: &PausedPacket, definitions: Option<&PacketDefinitions>) {
+        let Some(body) = parse_hex(&self.hex) else {
+            self.decoded = "The body is not valid hex".to_string();
+            self.fields.clear();
+            return;
+        };
+
+        let packet = with_body(packet, &body, definitions);
+        self.decoded = packet_to_string(&packet);
+        self.fields = match decode_fields(&packet) {
+            Some(Ok(root)) => root
+                .children
+                .iter()
+                .filter_map(|field| BodyField::read(field, &body))
+                .collect(),
+            _ => Vec::new(),
+        };
+        self.body = body;
+    }
+
+    /// Encodes the value of the field at `index` in place of its old value in the body.
+    fn write_field(
+        &mut self,
+        index: usize,
+        packet: &PausedPacket,
+        definitions: Option<&PacketDefinitions>,
+    ) {
+        let field = &mut self.fields[index];
+        let mut encoded = Vec::new();
+        if let Err(e) = field.kind.encode(&field.value, &mut encoded) {
+            field.error = Some(format!("{:#}", e));
+            return;
+        }
+        field.error = None;
+
+        let old = field.range.clone();
+        let tail = self.body.split_off(old.end);
+        self.body.truncate(old.start);
+        self.body.extend_from_slice(&encoded);
+        self.body.extend(tail);
+        field.range = old.start..old.start + encoded.len();
+
+        // the fields after it moved along with the rest of the body
+        for later in &mut self.fields[index + 1..] {
+            later.range = later.range.start - old.len() + encoded.len()
+                ..later.range.end - old.len() + encoded.len();
+        }
+
+        self.hex = format_hex(&self.body);
+        self.decoded = packet_to_string(&with_body(packet, &self.body, definitions));
+    }
+}
+
+fn draw_rules(ui: &mut egui::Ui, rules: &mut Vec<Breakpoint>) {
+    ui.horizontal(|ui| {
+        ui.heading("Breakpoints");
+        if ui.button("Add").clicked() {
+            rules.push(Breakpoint::new());
+        }
+    });
+
+    let mut removed = None;
+    for (i, rule) in rules.iter_mut().enumerate() {
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut rule.enabled, "");
+            egui::ComboBox::from_id_source(("breakpoint_side", i))
+                .selected_text(match rule.side {
+                    Some(side) => direction(side),
+                    None => "Any",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut rule.side, None, "Any");
+                    ui.selectable_value(&mut rule.side, Some(PacketSide::Serverbound), "C → S");
+                    ui.selectable_value(&mut rule.side, Some(PacketSide::Clientbound), "S → C");
+                });
+            ui.add(egui::TextEdit::singleline(&mut rule.target).hint_text("Packet name or id"));
+            if ui.button("Remove").clicked() {
+                removed = Some(i);
+            }
+        });
+    }
+
+    if let Some(i) = removed {
+        rules.remove(i);
+    }
+}
+
+pub(super) fn direction(side: PacketSide) -> &'static str {
+    match side {
+        PacketSide::Serverbound => "C → S",
+        PacketSide::Clientbound => "S → C",
+    }
+}
+
+/// The paused packet with `body` in place of its own, laid out by `definitions` if they apply.
+fn with_body(
+    packet: &PausedPacket,
+    body: &[u8],
+    definitions: Option<&PacketDefinitions>,
+) -> Packet {
+    let mut packet = Packet {
+        side: packet.side,
+        state: packet.state,
+        id: packet.id,
+        timestamp: None,
+        name: packet.name,
+        data: Some(body.to_vec().into()),
+        session: Some(packet.session.clone()),
+        schema: None,
+    };
+
+    if let Some(definitions) = definitions {
+        apply_definitions(definitions, &mut packet);
+    }
+
+    packet
+}
@@ -1,6 +1,17 @@
-use crate::shared_state::Event;
+use proxy_lib::{packet_table, Session};
 
-use super::{SharedState, Tab, View};
+use crate::{
+    packet_buffer::Retention,
+    shared_state::{Event, SessionEntry},
+};
+
+use super::{
+    breakpoints::direction,
+    packet_list::{systemtime_strftime, CAPTURE_EXTENSION, CAPTURE_FILTER_NAME},
+    SharedState, Tab, View,
+};
+
+const ONLINE_MODE_LABEL: &str = "Decrypt online-mode logins";
 
 pub struct Connection {}
 
@@ -21,6 +32,7 @@ impl View for Connection {
             ui.text_edit_singleline(&mut state.listener_addr.clone());
             ui.label("Server Address");
             ui.text_edit_singleline(&mut state.server_addr.clone());
+            ui.checkbox(&mut state.online_mode.clone(), ONLINE_MODE_LABEL);
             if ui.button("Stop Listening").clicked() {
                 state.send_event(Event::StopListening);
             }
@@ -29,9 +41,234 @@ impl View for Connection {
             ui.text_edit_singleline(&mut state.listener_addr);
             ui.label("Server Address");
             ui.text_edit_singleline(&mut state.server_addr);
-            if ui.button("Start Listening").clicked() {
+            ui.checkbox(&mut state.online_mode, ONLINE_MODE_LABEL)
+                .on_hover_text("The server must not verify logins with Mojang");
+            if ui
+                .add_enabled(!state.is_replaying, egui::Button::new("Start Listening"))
+                .clicked()
+            {
                 state.send_event(Event::StartListening);
             }
         }
+
+        ui.separator();
+        draw_definitions(ui, state);
+
+        ui.separator();
+        draw_capture_buffer(ui, state);
+
+        ui.separator();
+        draw_replay(ui, state);
+
+        ui.separator();
+        draw_sessions(ui, state);
+    }
+}
+
+fn draw_definitions(ui: &mut egui::Ui, state: &mut SharedState) {
+    ui.label("Packet Definitions");
+    match (&state.definitions, &state.definitions_path) {
+        (Some(definitions), Some(path)) => ui.label(format!(
+            "{} packets for protocol {} from {}",
+            definitions.packets.len(),
+            definitions.protocol_version,
+            path.display()
+        )),
+        _ => ui.label("None loaded"),
+    };
+
+    ui.horizontal(|ui| {
+        ui.label("Protocol");
+        ui.add(egui::DragValue::new(&mut state.definitions_protocol));
+    });
+
+    ui.horizontal(|ui| {
+        if ui.button("Load packet definitions…").clicked() {
+            if let Some(path) = rfd::FileDialog::new()
+                .add_filter("Packet definitions", &["json"])
+                .pick_file()
+            {
+                if let Err(e) = state.load_definitions(&path) {
+                    tracing::error!("Failed to load packet definitions: {:?}", e);
+                }
+            }
+        }
+
+        if state.definitions.is_some() && ui.button("Forget").clicked() {
+            state.forget_definitions();
+        }
+    });
+}
+
+fn draw_capture_buffer(ui: &mut egui::Ui, state: &mut SharedState) {
+    ui.label("Capture Buffer");
+
+    // remembered across modes, so switching back and forth keeps the numbers
+    let mut retention = state.retention;
+    let packets = match retention {
+        Retention::Packets(n) | Retention::Spill(n) => n,
+        _ => 100_000,
+    };
+    let megabytes = match retention {
+        Retention::Megabytes(mb) => mb,
+        _ => 512,
+    };
+
+    egui::ComboBox::from_id_source("retention")
+        .selected_text(retention_label(retention))
+        .show_ui(ui, |ui| {
+            for option in [
+                Retention::Unbounded,
+                Retention::Packets(packets),
+                Retention::Megabytes(megabytes),
+                Retention::Spill(packets),
+            ] {
+                ui.selectable_value(&mut retention, option, retention_label(option));
+            }
+        });
+
+    match &mut retention {
+        Retention::Unbounded => {}
+        Retention::Packets(n) | Retention::Spill(n) => {
+            ui.horizontal(|ui| {
+                ui.label("Packets in memory");
+                ui.add(egui::DragValue::new(n).clamp_range(1..=100_000_000));
+            });
+        }
+        Retention::Megabytes(mb) => {
+            ui.horizontal(|ui| {
+                ui.label("Megabytes");
+                ui.add(egui::DragValue::new(mb).clamp_range(1..=1_000_000));
+            });
+        }
+    }
+
+    if retention != state.retention {
+        state.set_retention(retention);
+    }
+
+    let packets = state.packets.read().unwrap();
+    let (in_memory, bytes) = packets.in_memory();
+    ui.label(format!(
+        "{} packets in memory ({:.1} MB), {} on disk, {} dropped",
+        in_memory,
+        bytes as f64 / (1024.0 * 1024.0),
+        packets.spilled(),
+        packets.dropped()
+    ));
+
+    if packets.retention() != state.retention {
+        ui.colored_label(
+            ui.visuals().warn_fg_color,
+            "Spilling to disk failed, older packets are dropped instead",
+        );
+    }
+}
+
+fn retention_label(retention: Retention) -> &'static str {
+    match retention {
+        Retention::Unbounded => "Keep every packet",
+        Retention::Packets(_) => "Keep the newest packets",
+        Retention::Megabytes(_) => "Keep the newest megabytes",
+        Retention::Spill(_) => "Move older packets to disk",
+    }
+}
+
+fn draw_replay(ui: &mut egui::Ui, state: &mut SharedState) {
+    ui.label("Replay Speed");
+    ui.add(
+        egui::Slider::new(&mut state.replay_speed, 0.1..=20.0)
+            .logarithmic(true)
+            .suffix("x"),
+    );
+
+    if state.is_replaying {
+        if ui.button("Stop Replay").clicked() {
+            state.send_event(Event::StopReplay);
+        }
+    } else if ui
+        .add_enabled(!state.is_listening, egui::Button::new("Replay capture…"))
+        .clicked()
+    {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter(CAPTURE_FILTER_NAME, &[CAPTURE_EXTENSION])
+            .pick_file()
+        {
+            state.send_event(Event::StartReplay(path));
+        }
     }
 }
+
+fn draw_sessions(ui: &mut egui::Ui, state: &SharedState) {
+    let sessions = state.sessions.read().unwrap();
+
+    ui.label(format!("Sessions ({})", sessions.len()));
+    for entry in sessions.iter() {
+        let status = match &entry.closed {
+            Some(reason) => format!("closed ({})", reason),
+            None => "open".to_string(),
+        };
+
+        ui.label(format!(
+            "{} {} @ {} - {} - {}",
+            entry.session.id,
+            entry.session.client_addr,
+            systemtime_strftime(entry.session.accepted_at),
+            protocol_label(&entry.session),
+            status
+        ));
+
+        draw_trailing_report(ui, entry);
+    }
+}
+
+/// Minecraft version the session negotiated in its handshake.
+pub(super) fn protocol_label(session: &Session) -> String {
+    match session.protocol_version() {
+        Some(version) => match packet_table(version) {
+            Some(table) => format!("{} ({})", table.version, version),
+            None => format!("protocol {} (unsupported)", version),
+        },
+        None => "no handshake".to_string(),
+    }
+}
+
+/// Packet types of the session that decoded with bytes left over, the ones that always do first.
+fn draw_trailing_report(ui: &mut egui::Ui, entry: &SessionEntry) {
+    let mut report = entry
+        .trailing
+        .iter()
+        .filter(|(_, stats)| stats.with_trailing > 0)
+        .collect::<Vec<_>>();
+
+    if report.is_empty() {
+        return;
+    }
+
+    report.sort_by_key(|((side, state, id), stats)| {
+        (!stats.is_consistent(), *side as u8, *state as u8, *id)
+    });
+
+    egui::CollapsingHeader::new(format!("Trailing data ({} packet types)", report.len()))
+        .id_source(("trailing_report", entry.session.id))
+        .show(ui, |ui| {
+            for ((side, state, id), stats) in report {
+                let text = format!(
+                    "{} [{:?}] 0x{:02X} {}: {}/{} packets, up to {} bytes",
+                    direction(*side),
+                    state,
+                    id,
+                    stats.name,
+                    stats.with_trailing,
+                    stats.decoded,
+                    stats.max_trailing
+                );
+
+                if stats.is_consistent() {
+                    ui.colored_label(ui.visuals().warn_fg_color, text);
+                } else {
+                    ui.label(text);
+                }
+            }
+        });
+}
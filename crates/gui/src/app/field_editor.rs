@@ -0,0 +1,185 @@
+//! Typing in the values of single packet fields and encoding them, for the fields of packets
+//! built in the Inject tab and of packets paused by a breakpoint.
+
+use anyhow::{bail, Context};
+use proxy_lib::parse_hex;
+use valence::protocol::{var_int::VarInt, var_long::VarLong, Decode, Encode};
+
+/// How a field's value is typed in and encoded, picked from the field's type.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum FieldKind {
+    VarInt,
+    VarLong,
+    String,
+    Bool,
+    Byte,
+    UnsignedByte,
+    Short,
+    UnsignedShort,
+    Int,
+    Long,
+    Float,
+    Double,
+    Uuid,
+    /// Block position typed in as `x y z`, packed into a long
+    Position,
+    /// VarInt length prefixed bytes, typed in as hex
+    ByteArray,
+    /// The encoded value as hex, for every other type
+    Raw,
+}
+
+impl FieldKind {
+    /// The kind for a type from [`proxy_lib::packet_layout`] or of a decoded
+    /// [`proxy_lib::Field`]. Valence's and definitions files' names are both understood.
+    pub(super) fn of(ty: &str) -> Self {
+        match ty {
+            "VarInt" => Self::VarInt,
+            "VarLong" => Self::VarLong,
+            "String" | "str" | "&str" | "Cow<str>" => Self::String,
+            // identifiers are sent as plain strings
+            ty if ty.starts_with("Ident<") => Self::String,
+            "Bool" | "bool" => Self::Bool,
+            "Byte" | "i8" => Self::Byte,
+            "UnsignedByte" | "u8" => Self::UnsignedByte,
+            "Short" | "i16" => Self::Short,
+            "UnsignedShort" | "u16" => Self::UnsignedShort,
+            "Int" | "i32" => Self::Int,
+            "Long" | "i64" => Self::Long,
+            "Float" | "f32" => Self::Float,
+            "Double" | "f64" => Self::Double,
+            "Uuid" | "uuid" => Self::Uuid,
+            "Position" | "BlockPos" => Self::Position,
+            "ByteArray" | "&[u8]" | "Vec<u8>" => Self::ByteArray,
+            _ => Self::Raw,
+        }
+    }
+
+    /// Value a new field of this kind starts out with.
+    pub(super) fn default_value(self) -> String {
+        match self {
+            Self::String | Self::ByteArray | Self::Raw => String::new(),
+            Self::Bool => "false".to_string(),
+            Self::Float | Self::Double => "0.0".to_string(),
+            Self::Uuid => "00000000-0000-0000-0000-000000000000".to_string(),
+            Self::Position => "0 0 0".to_string(),
+            _ => "0".to_string(),
+        }
+    }
+
+    /// The encoded `bytes` of a field as its value is typed in. `None` if they aren't exactly one
+    /// value of this kind.
+    pub(super) fn decode(self, bytes: &[u8]) -> Option<String> {
+        let mut r = bytes;
+
+        let value = match self {
+            Self::VarInt => VarInt::decode(&mut r).ok()?.0.to_string(),
+            Self::VarLong => VarLong::decode(&mut r).ok()?.0.to_string(),
+            Self::String => <&str>::decode(&mut r).ok()?.to_string(),
+            Self::Bool => bool::decode(&mut r).ok()?.to_string(),
+            Self::Byte => i8::decode(&mut r).ok()?.to_string(),
+            Self::UnsignedByte => u8::decode(&mut r).ok()?.to_string(),
+            Self::Short => i16::decode(&mut r).ok()?.to_string(),
+            Self::UnsignedShort => u16::decode(&mut r).ok()?.to_string(),
+            Self::Int => i32::decode(&mut r).ok()?.to_string(),
+            Self::Long => i64::decode(&mut r).ok()?.to_string(),
+            Self::Float => format!("{:?}", f32::decode(&mut r).ok()?),
+            Self::Double => format!("{:?}", f64::decode(&mut r).ok()?),
+            Self::Uuid => {
+                let uuid = u128::from_be_bytes(r.try_into().ok()?);
+                r = &[];
+                let hex = format!("{:032x}", uuid);
+                format!(
+                    "{}-{}-{}-{}-{}",
+                    &hex[..8],
+                    &hex[8..12],
+                    &hex[12..16],
+                    &hex[16..20],
+                    &hex[20..]
+                )
+            }
+            Self::Position => {
+                let packed = i64::decode(&mut r).ok()?;
+                let (x, y, z) = (packed >> 38, packed << 52 >> 52, packed << 26 >> 38);
+                format!("{} {} {}", x, y, z)
+            }
+            Self::ByteArray => hex(<&[u8]>::decode(&mut r).ok()?),
+            Self::Raw => hex(std::mem::take(&mut r)),
+        };
+
+        r.is_empty().then_some(value)
+    }
+
+    pub(super) fn encode(self, value: &str, out: &mut Vec<u8>) -> anyhow::Result<()> {
+        let trimmed = value.trim();
+
+        match self {
+            Self::VarInt => VarInt(trimmed.parse()?).encode(out)?,
+            Self::VarLong => VarLong(trimmed.parse()?).encode(out)?,
+            // strings are taken as typed, surrounding whitespace included
+            Self::String => value.encode(out)?,
+            Self::Bool => trimmed.parse::<bool>()?.encode(out)?,
+            Self::Byte => trimmed.parse::<i8>()?.encode(out)?,
+            Self::UnsignedByte => trimmed.parse::<u8>()?.encode(out)?,
+            Self::Short => trimmed.parse::<i16>()?.encode(out)?,
+            Self::UnsignedShort => trimmed.parse::<u16>()?.encode(out)?,
+            Self::Int => trimmed.parse::<i32>()?.encode(out)?,
+            Self::Long => trimmed.parse::<i64>()?.encode(out)?,
+            Self::Float => trimmed.parse::<f32>()?.encode(out)?,
+            Self::Double => trimmed.parse::<f64>()?.encode(out)?,
+            Self::Uuid => {
+                let uuid = u128::from_str_radix(&trimmed.replace('-', ""), 16)?;
+                out.extend_from_slice(&uuid.to_be_bytes());
+            }
+            Self::Position => {
+                let coords = trimmed
+                    .split(|c: char| c == ',' || c.is_whitespace())
+                    .filter(|s| !s.is_empty())
+                    .map(str::parse::<i64>)
+                    .collect::<Result<Vec<_>, _>>()?;
+                let [x, y, z] = coords[..] else {
+                    bail!("expected x y z");
+                };
+                let packed = ((x & 0x3ff_ffff) << 38) | ((z & 0x3ff_ffff) << 12) | (y & 0xfff);
+                packed.encode(out)?;
+            }
+            Self::ByteArray => {
+                let bytes = parse_hex(trimmed).context("invalid hex")?;
+                VarInt(bytes.len().try_into()?).encode(out)?;
+                out.extend(bytes);
+            }
+            Self::Raw => out.extend(parse_hex(trimmed).context("invalid hex")?),
+        }
+
+        Ok(())
+    }
+
+    /// An input for `value` fitting the kind.
+    pub(super) fn edit(self, ui: &mut egui::Ui, value: &mut String) -> egui::Response {
+        if self == Self::Bool {
+            let mut checked = value.trim() == "true";
+            let response = ui.checkbox(&mut checked, "");
+            if response.changed() {
+                *value = checked.to_string();
+            }
+            return response;
+        }
+
+        let hint = match self {
+            Self::Uuid => "00000000-0000-0000-0000-000000000000",
+            Self::Position => "x y z",
+            Self::ByteArray | Self::Raw => "hex",
+            _ => "",
+        };
+        ui.add(egui::TextEdit::singleline(value).hint_text(hint))
+    }
+}
+
+/// `bytes` as hex on a single line, for the inputs of byte fields.
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -1,16 +1,24 @@
 use egui::{RichText, Ui, Widget};
 use itertools::Itertools;
-use proxy_lib::PacketState;
+use proxy_lib::{DisplayFilter, PacketState};
 
 use crate::tri_checkbox::{TriCheckbox, TriCheckboxState};
 
 use super::{SharedState, Tab, View};
 
-pub struct Filter {}
+pub struct Filter {
+    /// Display filter being edited, starts out as the one in effect
+    expression: Option<String>,
+    /// Why the expression couldn't be applied
+    error: Option<String>,
+}
 
 impl Tab for Filter {
     fn new() -> Self {
-        Self {}
+        Self {
+            expression: None,
+            error: None,
+        }
     }
 
     fn name(&self) -> &'static str {
@@ -20,13 +28,67 @@ impl Tab for Filter {
 
 impl View for Filter {
     fn ui(&mut self, ui: &mut egui::Ui, state: &mut SharedState) {
-        draw_packet_list(ui, state, PacketState::Handshaking);
+        self.draw_display_filter(ui, state);
+        ui.separator();
+
+        let mut changed = ui
+            .checkbox(&mut state.decode_errors_only, "Only decode errors")
+            .changed();
+        ui.separator();
+        changed |= draw_packet_list(ui, state, PacketState::Handshaking);
         ui.separator();
-        draw_packet_list(ui, state, PacketState::Status);
+        changed |= draw_packet_list(ui, state, PacketState::Status);
         ui.separator();
-        draw_packet_list(ui, state, PacketState::Login);
+        changed |= draw_packet_list(ui, state, PacketState::Login);
         ui.separator();
-        draw_packet_list(ui, state, PacketState::Play);
+        changed |= draw_packet_list(ui, state, PacketState::Play);
+
+        if changed {
+            state.refilter();
+        }
+    }
+}
+
+impl Filter {
+    fn draw_display_filter(&mut self, ui: &mut Ui, state: &mut SharedState) {
+        let expression = self.expression.get_or_insert_with(|| {
+            state
+                .display_filter()
+                .map_or_else(String::new, |filter| filter.as_str().to_string())
+        });
+
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(expression)
+                    .hint_text("Display filter, like name ~ \"Move\" && len > 100"),
+            );
+            let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+
+            if ui.button("Apply").clicked() || submitted {
+                let filter = match expression.trim() {
+                    "" => Ok(None),
+                    expression => DisplayFilter::parse(expression).map(Some),
+                };
+
+                match filter {
+                    Ok(filter) => {
+                        state.set_display_filter(filter);
+                        self.error = None;
+                    }
+                    Err(e) => self.error = Some(format!("{:#}", e)),
+                }
+            }
+
+            if state.display_filter().is_some() && ui.button("Clear").clicked() {
+                expression.clear();
+                state.set_display_filter(None);
+                self.error = None;
+            }
+        });
+
+        if let Some(error) = &self.error {
+            ui.colored_label(ui.visuals().error_fg_color, error);
+        }
     }
 }
 
@@ -53,7 +115,8 @@ fn get_checkbox_state(state: &SharedState, packet_state: PacketState) -> TriChec
     }
 }
 
-fn draw_packet_list(ui: &mut Ui, state: &mut SharedState, packet_state: PacketState) {
+/// Returns whether any packet was switched on or off.
+fn draw_packet_list(ui: &mut Ui, state: &mut SharedState, packet_state: PacketState) -> bool {
     let title = match packet_state {
         PacketState::Handshaking => "Handshaking",
         PacketState::Status => "Status",
@@ -62,10 +125,10 @@ fn draw_packet_list(ui: &mut Ui, state: &mut SharedState, packet_state: PacketSt
     };
 
     let mut checkbox = get_checkbox_state(state, packet_state);
-    if TriCheckbox::new(&mut checkbox, RichText::new(title).heading().strong())
+    let mut changed = TriCheckbox::new(&mut checkbox, RichText::new(title).heading().strong())
         .ui(ui)
-        .changed()
-    {
+        .changed();
+    if changed {
         for (_, enabled) in state
             .packet_filter
             .iter_mut()
@@ -84,6 +147,10 @@ fn draw_packet_list(ui: &mut Ui, state: &mut SharedState, packet_state: PacketSt
         .filter(|(p, _)| p.state == packet_state)
         .sorted_by(|(a, _), (b, _)| a.id.cmp(&b.id))
     {
-        ui.checkbox(enabled, format!("[0x{:0>2X}] {}", p.id, p.name));
+        changed |= ui
+            .checkbox(enabled, format!("[0x{:0>2X}] {}", p.id, p.name))
+            .changed();
     }
+
+    changed
 }
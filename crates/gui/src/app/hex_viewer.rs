@@ -1,9 +1,16 @@
-use std::io::Read;
-
-use egui::Color32;
+use egui::{Color32, Label, RichText, Sense};
+use proxy_lib::Field;
 
 use super::{SharedState, Tab, View};
 
+const DEFAULT_COLOR: Color32 = Color32::from_rgb(255, 255, 255);
+const VAR_INT_COLOR: Color32 = Color32::from_rgb(120, 170, 255);
+const STRING_COLOR: Color32 = Color32::from_rgb(140, 220, 140);
+const NBT_COLOR: Color32 = Color32::from_rgb(240, 170, 80);
+const UNKNOWN_COLOR: Color32 = Color32::from_rgb(240, 100, 100);
+/// Background of what the search found
+pub(super) const SEARCH_COLOR: Color32 = Color32::from_rgb(110, 90, 0);
+
 pub struct HexView {}
 
 impl Tab for HexView {
@@ -18,16 +25,47 @@ impl Tab for HexView {
 
 impl View for HexView {
     fn ui(&mut self, ui: &mut egui::Ui, state: &mut SharedState) {
-        let mut buf = [0u8; 16];
-        let mut count = 0;
+        let fields = state.selected_fields();
 
-        let packets = state.packets.read().unwrap();
         let Some(packet_index) = state.selected_packet else {
             return;
         };
 
-        let bytes = &packets[packet_index].data.as_ref().unwrap();
-        let mut file = &bytes.clone()[..];
+        let Some(packet) = state.packet(packet_index) else {
+            return;
+        };
+        let bytes = packet.data.unwrap_or_default();
+
+        let colors = byte_colors(fields.as_deref(), bytes.len());
+        let highlight = state
+            .hovered_field
+            .clone()
+            .or_else(|| state.selected_field.clone());
+        let found = match state.search.read().unwrap().pattern() {
+            Some(pattern) => pattern.body_ranges(&bytes),
+            None => Vec::new(),
+        };
+
+        let mut hovered = None;
+        let mut clicked = None;
+        let mut byte_label = |ui: &mut egui::Ui, text: String, offset: usize| {
+            let mut text = RichText::new(text).color(colors[offset]);
+            if highlight.as_ref().map_or(false, |r| r.contains(&offset)) {
+                text = text.background_color(ui.visuals().selection.bg_fill);
+            } else if found.iter().any(|r| r.contains(&offset)) {
+                text = text.background_color(SEARCH_COLOR);
+            }
+
+            let response = ui.add(Label::new(text).sense(Sense::click()));
+            if response.hovered() {
+                hovered = Some(offset);
+            }
+            if response.clicked() {
+                clicked = Some(offset);
+            }
+        };
+
+        draw_legend(ui);
 
         egui::Grid::new("hex_grid")
             .spacing([4.0, 1.5])
@@ -39,31 +77,105 @@ impl View for HexView {
                     ui.label(format!("{:02X}", i));
                 }
                 ui.end_row();
-                loop {
-                    let bytes_read = file.read(&mut buf).unwrap();
-                    if bytes_read == 0 {
-                        break;
-                    }
 
-                    ui.label(format!("{:08X}", count));
-                    for b in buf.iter().take(bytes_read) {
-                        ui.colored_label(Color32::from_rgb(255, 255, 255), format!("{:02X}", b));
+                for (row, line) in bytes.chunks(16).enumerate() {
+                    let start = row * 16;
+
+                    ui.label(format!("{:08X}", start));
+                    for (i, b) in line.iter().enumerate() {
+                        byte_label(ui, format!("{:02X}", b), start + i);
                     }
-                    for _ in 0..16 - bytes_read {
+                    for _ in line.len()..16 {
                         ui.label(" ");
                     }
                     ui.label(" ");
-                    for b in buf.iter().take(bytes_read) {
-                        if *b >= 0x20 && *b <= 0x7e {
-                            ui.label(format!("{}", *b as char));
+                    for (i, b) in line.iter().enumerate() {
+                        let c = if (0x20..=0x7e).contains(b) {
+                            *b as char
                         } else {
-                            ui.label(".");
-                        }
+                            '.'
+                        };
+                        byte_label(ui, c.to_string(), start + i);
                     }
 
                     ui.end_row();
-                    count += bytes_read;
                 }
             });
+
+        state.hovered_byte = hovered;
+        if let (Some(offset), Some(root)) = (clicked, &fields) {
+            state.selected_field = root
+                .find_at(offset)
+                .filter(|field| !std::ptr::eq(*field, &**root))
+                .and_then(Field::range);
+        }
     }
 }
+
+fn draw_legend(ui: &mut egui::Ui) {
+    ui.horizontal(|ui| {
+        ui.colored_label(VAR_INT_COLOR, "VarInt");
+        ui.colored_label(STRING_COLOR, "String");
+        ui.colored_label(NBT_COLOR, "NBT");
+        ui.colored_label(UNKNOWN_COLOR, "Unknown / trailing");
+    });
+}
+
+/// Color for the bytes of a decoded field, also used for it in the Text Viewer.
+pub(super) fn field_color(field: &Field) -> Color32 {
+    match field.ty.as_str() {
+        "VarInt" | "VarLong" => VAR_INT_COLOR,
+        "str" | "&str" | "String" | "Cow<str>" => STRING_COLOR,
+        ty if ty.starts_with("Ident<") => STRING_COLOR,
+        ty if ty.contains("Compound") || ty.contains("Nbt") => NBT_COLOR,
+        _ => DEFAULT_COLOR,
+    }
+}
+
+fn byte_colors(root: Option<&Field>, len: usize) -> Vec<Color32> {
+    let mut colors = vec![DEFAULT_COLOR; len];
+    let Some(root) = root else {
+        return colors;
+    };
+
+    // bytes past what the decoder consumed aren't part of the packet as it knows it
+    if let Some(range) = root.range() {
+        for color in &mut colors[range.end.min(len)..] {
+            *color = UNKNOWN_COLOR;
+        }
+    }
+
+    paint_fields(root, &mut colors);
+    colors
+}
+
+/// Inner fields are painted over their parents, uncolored ones keep the parent's color.
+fn paint_fields(field: &Field, colors: &mut [Color32]) {
+    for child in &field.children {
+        let Some(range) = child.range() else {
+            continue;
+        };
+
+        let color = field_color(child);
+        if color != DEFAULT_COLOR {
+            for c in &mut colors[range.start.min(colors.len())..range.end.min(colors.len())] {
+                *c = color;
+            }
+        }
+
+        paint_fields(child, colors);
+    }
+}
+
+pub(super) fn format_hex(bytes: &[u8]) -> String {
+    bytes
+        .chunks(16)
+        .map(|line| {
+            line.iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,302 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context};
+use proxy_lib::{
+    packet_layout, packet_table, packet_to_string, parse_hex, Packet, PacketDefinitions,
+    PacketSide, Session, SessionId, STD_PACKETS, STD_PROTOCOL_VERSION,
+};
+
+use super::{
+    breakpoints::direction, field_editor::FieldKind, text_viewer::code_view_ui, SharedState, Tab,
+    View,
+};
+
+/// One field of the selected packet, with the value typed in for it.
+struct FormField {
+    name: String,
+    ty: String,
+    kind: FieldKind,
+    value: String,
+}
+
+pub struct Inject {
+    session: Option<SessionId>,
+    /// Protocol version and definitions `packets` was built from
+    version: Option<i32>,
+    definitions: Option<Arc<PacketDefinitions>>,
+    /// Bundled packets of the session's protocol version, with the definitions for it applied
+    packets: Vec<Packet>,
+    side: PacketSide,
+    /// Index into `packets`
+    packet: Option<usize>,
+    raw: bool,
+    /// Fields of the selected packet, `None` if they aren't known
+    fields: Option<Vec<FormField>>,
+    hex: String,
+    status: String,
+}
+
+impl Tab for Inject {
+    fn new() -> Self {
+        Self {
+            session: None,
+            version: None,
+            definitions: None,
+            packets: STD_PACKETS.to_vec(),
+            side: PacketSide::Clientbound,
+            packet: None,
+            raw: false,
+            fields: None,
+            hex: String::new(),
+            status: String::new(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        "Inject"
+    }
+}
+
+impl View for Inject {
+    fn ui(&mut self, ui: &mut egui::Ui, state: &mut SharedState) {
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                let session = self.draw_target(ui, state);
+
+                ui.horizontal(|ui| {
+                    ui.radio_value(&mut self.raw, false, "Fields");
+                    ui.radio_value(&mut self.raw, true, "Raw hex");
+                });
+
+                if self.raw {
+                    ui.add(
+                        egui::TextEdit::multiline(&mut self.hex)
+                            .font(egui::TextStyle::Monospace)
+                            .code_editor()
+                            .desired_width(ui.available_width())
+                            .desired_rows(8),
+                    );
+                } else {
+                    self.draw_fields(ui);
+                }
+
+                let body = self.body();
+                let target = self.session.zip(self.packet);
+
+                let can_send = state.injector.is_some() && target.is_some() && body.is_ok();
+                if ui
+                    .add_enabled(can_send, egui::Button::new("Send"))
+                    .on_disabled_hover_text("Needs a live session, a packet and a valid body")
+                    .clicked()
+                {
+                    if let (Some(injector), Some((session, packet)), Ok(body)) =
+                        (&state.injector, target, &body)
+                    {
+                        let packet = &self.packets[packet];
+                        self.status = match injector.inject(session, self.side, packet.id, body) {
+                            Ok(()) => format!("Sent {} to session {}", packet.name, session),
+                            Err(e) => format!("Failed to send: {}", e),
+                        };
+                    }
+                }
+                ui.label(&self.status);
+
+                ui.separator();
+                ui.label("Decoded");
+                let decoded = match (self.packet, &body) {
+                    (None, _) => String::new(),
+                    (Some(packet), Ok(body)) => packet_to_string(&Packet {
+                        data: Some(body.clone().into()),
+                        session,
+                        ..self.packets[packet].clone()
+                    }),
+                    (_, Err(e)) => format!("Invalid body: {:#}", e),
+                };
+                code_view_ui(ui, &decoded);
+            });
+    }
+}
+
+impl Inject {
+    /// Draws the session and packet pickers, returning the selected session.
+    fn draw_target(&mut self, ui: &mut egui::Ui, state: &SharedState) -> Option<Arc<Session>> {
+        let sessions = state.sessions.read().unwrap();
+        let open = sessions
+            .iter()
+            .filter(|e| e.closed.is_none())
+            .collect::<Vec<_>>();
+
+        let session = open
+            .iter()
+            .find(|e| Some(e.session.id) == self.session)
+            .map(|e| e.session.clone());
+        if session.is_none() {
+            self.session = None;
+        }
+
+        let version = session.as_ref().and_then(|s| s.protocol_version());
+        let definitions_changed = match (&self.definitions, &state.definitions) {
+            (Some(old), Some(new)) => !Arc::ptr_eq(old, new),
+            (old, new) => old.is_some() != new.is_some(),
+        };
+        if version != self.version || definitions_changed {
+            self.version = version;
+            self.definitions = state.definitions.clone();
+            self.packets = packet_list(version, self.definitions.as_deref());
+            self.packet = None;
+            self.fields = None;
+        }
+
+        // packets of other states would be misread by the peer
+        let current_state = session.as_ref().map(|s| s.state());
+        if let (Some(i), Some(current)) = (self.packet, current_state) {
+            if self.packets[i].state != current {
+                self.packet = None;
+                self.fields = None;
+            }
+        }
+
+        let selected = self.packet;
+
+        egui::Grid::new("inject_target").show(ui, |ui| {
+            ui.label("Session");
+            egui::ComboBox::from_id_source("inject_session")
+                .selected_text(match self.session {
+                    Some(id) => id.to_string(),
+                    None => "None".to_string(),
+                })
+                .show_ui(ui, |ui| {
+                    for entry in &open {
+                        ui.selectable_value(
+                            &mut self.session,
+                            Some(entry.session.id),
+                            format!("{} {}", entry.session.id, entry.session.client_addr),
+                        );
+                    }
+                });
+            ui.end_row();
+
+            ui.label("Direction");
+            ui.horizontal(|ui| {
+                for side in [PacketSide::Serverbound, PacketSide::Clientbound] {
+                    if ui
+                        .radio_value(&mut self.side, side, direction(side))
+                        .changed()
+                    {
+                        self.packet = None;
+                    }
+                }
+            });
+            ui.end_row();
+
+            ui.label("Packet");
+            egui::ComboBox::from_id_source("inject_packet")
+                .width(250.0)
+                .selected_text(match self.packet {
+                    Some(i) => self.packets[i].name,
+                    None => "None",
+                })
+                .show_ui(ui, |ui| {
+                    for (i, packet) in self.packets.iter().enumerate() {
+                        if packet.side != self.side
+                            || current_state.map_or(false, |state| packet.state != state)
+                        {
+                            continue;
+                        }
+
+                        ui.selectable_value(
+                            &mut self.packet,
+                            Some(i),
+                            format!("[{:?}] 0x{:02X} {}", packet.state, packet.id, packet.name),
+                        );
+                    }
+                });
+            ui.end_row();
+        });
+
+        if self.packet != selected {
+            self.fields = self.packet.and_then(|i| {
+                let layout = packet_layout(&Packet {
+                    session: session.clone(),
+                    ..self.packets[i].clone()
+                })?;
+
+                let fields = layout
+                    .into_iter()
+                    .map(|field| {
+                        let kind = FieldKind::of(&field.ty);
+                        FormField {
+                            name: field.name,
+                            ty: field.ty,
+                            kind,
+                            value: kind.default_value(),
+                        }
+                    })
+                    .collect();
+                Some(fields)
+            });
+        }
+
+        session
+    }
+
+    fn draw_fields(&mut self, ui: &mut egui::Ui) {
+        if self.packet.is_none() {
+            return;
+        }
+
+        let Some(fields) = &mut self.fields else {
+            ui.label("The fields of this packet aren't known, use raw hex");
+            return;
+        };
+
+        if fields.is_empty() {
+            ui.label("This packet has no fields");
+            return;
+        }
+
+        egui::Grid::new("inject_fields").show(ui, |ui| {
+            for field in fields {
+                ui.label(&field.name);
+                ui.weak(&field.ty);
+                field.kind.edit(ui, &mut field.value);
+                ui.end_row();
+            }
+        });
+    }
+
+    fn body(&self) -> anyhow::Result<Vec<u8>> {
+        if self.raw {
+            return parse_hex(&self.hex).context("invalid hex");
+        }
+
+        let Some(fields) = &self.fields else {
+            bail!("the fields of this packet aren't known");
+        };
+
+        let mut body = Vec::new();
+        for field in fields {
+            field
+                .kind
+                .encode(&field.value, &mut body)
+                .with_context(|| format!("field {} is not a valid {}", field.name, field.ty))?;
+        }
+
+        Ok(body)
+    }
+}
+
+/// The packets that can be sent on a session of `version`, named and laid out by `definitions`
+/// if they are for that version.
+fn packet_list(version: Option<i32>, definitions: Option<&PacketDefinitions>) -> Vec<Packet> {
+    let packets = version
+        .and_then(packet_table)
+        .map_or(&STD_PACKETS[..], |table| table.packets);
+
+    let version = version.unwrap_or(STD_PROTOCOL_VERSION);
+    match definitions.filter(|d| d.protocol_version == version) {
+        Some(definitions) => definitions.overlay(packets),
+        None => packets.to_vec(),
+    }
+}
@@ -4,15 +4,33 @@ use egui::{
     WidgetText,
 };
 
-use proxy_lib::{Packet, PacketSide};
+use proxy_lib::{parse_hex, Packet, PacketSide};
 
-use super::{SharedState, Tab, View};
+use crate::search::{Pattern, Search};
 
-pub struct PacketList {}
+use super::{connection::protocol_label, hex_viewer::SEARCH_COLOR, SharedState, Tab, View};
+
+pub(super) const CAPTURE_FILTER_NAME: &str = "Packet capture";
+pub(super) const CAPTURE_EXTENSION: &str = "picf";
+
+/// Height of a row in the list, without the spacing between rows
+const ROW_HEIGHT: f32 = 24.0;
+
+pub struct PacketList {
+    query: String,
+    /// Look for the query as hex bytes rather than text
+    hex: bool,
+    /// Packet to bring into view, after jumping to a search match
+    scroll_to: Option<usize>,
+}
 
 impl Tab for PacketList {
     fn new() -> Self {
-        Self {}
+        Self {
+            query: String::new(),
+            hex: false,
+            scroll_to: None,
+        }
     }
 
     fn name(&self) -> &'static str {
@@ -27,54 +45,105 @@ impl View for PacketList {
         ui.horizontal(|ui| {
             ui.heading("Packets");
             draw_packet_counter(state, ui);
+            draw_session_selector(state, ui);
             draw_clear_button(state, ui);
+            draw_capture_buttons(state, ui);
         });
-        egui::ScrollArea::vertical()
-            .auto_shrink([false, false])
-            .stick_to_bottom(true)
-            .show(ui, |ui| {
-                draw_packet_list(state, ui);
+        self.draw_search_bar(state, ui);
+        draw_packet_list(state, ui, self.scroll_to.take());
+    }
+}
+
+impl PacketList {
+    fn draw_search_bar(&mut self, state: &mut SharedState, ui: &mut Ui) {
+        let pattern = match (self.query.trim(), self.hex) {
+            ("", _) => Ok(None),
+            (query, false) => Ok(Some(Pattern::Text(query.to_string()))),
+            (query, true) => parse_hex(query)
+                .map(|bytes| Some(Pattern::Bytes(bytes)))
+                .ok_or(()),
+        };
+
+        ui.horizontal(|ui| {
+            let response = ui.add(
+                egui::TextEdit::singleline(&mut self.query)
+                    .hint_text("Search names, bodies and decoded text"),
+            );
+            ui.checkbox(&mut self.hex, "Hex");
+
+            let Ok(pattern) = pattern else {
+                ui.colored_label(ui.visuals().error_fg_color, "Invalid hex");
+                return;
+            };
+
+            let submitted = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            let searched = state.search.read().unwrap().pattern() == pattern.as_ref();
+
+            if !searched && (submitted || ui.button("Find").clicked()) {
+                state.search(pattern);
+                self.jump(state, |search, selected| search.next(selected));
+                return;
+            }
+
+            let search = state.search.read().unwrap();
+            if search.pattern().is_none() {
+                return;
+            }
+
+            let position = state.selected_packet.and_then(|i| search.position(i));
+            ui.label(match position {
+                Some(position) => format!("{}/{}", position, search.len()),
+                None => format!("{} matches", search.len()),
             });
+            drop(search);
+
+            let shift = ui.input(|i| i.modifiers.shift);
+            if ui.button("Previous").clicked() || (submitted && shift) {
+                self.jump(state, |search, selected| search.previous(selected));
+            }
+            if ui.button("Next").clicked() || (submitted && !shift) {
+                self.jump(state, |search, selected| search.next(selected));
+            }
+        });
+    }
+
+    /// Selects the match `find` picks and scrolls to it.
+    fn jump(
+        &mut self,
+        state: &mut SharedState,
+        find: impl FnOnce(&Search, Option<usize>) -> Option<usize>,
+    ) {
+        let found = find(&state.search.read().unwrap(), state.selected_packet);
+        if let Some(index) = found {
+            state.selected_packet = Some(index);
+            self.scroll_to = Some(index);
+        }
     }
 }
 
 fn handle_keyboard_input(state: &mut SharedState, ui: &mut Ui) {
+    let visible = state.visible.read().unwrap();
+
     if ui.input(|i| i.key_pressed(egui::Key::ArrowUp)) {
         // select previous packet
-        let index = match state.selected_packet {
-            Some(index) => index,
-            None => 1, // 1, so previous goes to 0
+        let prev_index = match state.selected_packet {
+            Some(index) => visible.previous(index),
+            None => visible.first(),
         };
 
-        let packets = state.packets.read().unwrap();
-        let filtered_packets = packets
-            .iter()
-            .enumerate()
-            .filter(|(i, p)| *i < index && state.packet_filter.get(p).unwrap_or(true))
-            .map(|(i, _)| i)
-            .collect::<Vec<_>>();
-
-        if let Some(&prev_index) = filtered_packets.last() {
+        if let Some(prev_index) = prev_index {
             state.selected_packet = Some(prev_index);
         }
     }
 
     if ui.input(|i| i.key_pressed(egui::Key::ArrowDown)) {
         // select next packet
-        let index = match state.selected_packet {
-            Some(index) => index,
-            None => 0, // 0, so next goes to 1
+        let next_index = match state.selected_packet {
+            Some(index) => visible.next(index),
+            None => visible.first(),
         };
 
-        let packets = state.packets.read().unwrap();
-        let filtered_packets = packets
-            .iter()
-            .enumerate()
-            .filter(|(i, p)| *i > index && state.packet_filter.get(p).unwrap_or(true))
-            .map(|(i, _)| i)
-            .collect::<Vec<_>>();
-
-        if let Some(&next_index) = filtered_packets.first() {
+        if let Some(next_index) = next_index {
             state.selected_packet = Some(next_index);
         }
     }
@@ -83,57 +152,170 @@ fn handle_keyboard_input(state: &mut SharedState, ui: &mut Ui) {
 fn draw_packet_counter(state: &mut SharedState, ui: &mut Ui) {
     let packets = state.packets.read().unwrap();
     let length = packets.len();
-
-    let filtered_packets = packets
-        .iter()
-        .filter(|p| state.packet_filter.get(p).unwrap_or(true))
-        .count();
+    let filtered_packets = state.visible.read().unwrap().len();
 
     ui.label(format!("({}/{})", filtered_packets, length));
+
+    if packets.spilled() > 0 {
+        ui.label(format!("{} on disk", packets.spilled()))
+            .on_hover_text("Older packets are read back from disk when shown");
+    }
+
+    if packets.dropped() > 0 {
+        ui.colored_label(
+            ui.visuals().warn_fg_color,
+            format!("{} dropped", packets.dropped()),
+        )
+        .on_hover_text("The oldest packets were dropped to stay within the capture buffer limit");
+    }
+}
+
+fn draw_session_selector(state: &mut SharedState, ui: &mut Ui) {
+    let sessions = state.sessions.read().unwrap();
+
+    let selected_text = match state.session_filter {
+        Some(id) => id.to_string(),
+        None => "All sessions".to_string(),
+    };
+
+    let mut changed = false;
+    egui::ComboBox::from_id_source("session_filter")
+        .selected_text(selected_text)
+        .show_ui(ui, |ui| {
+            changed |= ui
+                .selectable_value(&mut state.session_filter, None, "All sessions")
+                .changed();
+            for entry in sessions.iter() {
+                changed |= ui
+                    .selectable_value(
+                        &mut state.session_filter,
+                        Some(entry.session.id),
+                        format!(
+                            "{} {} {}",
+                            entry.session.id,
+                            entry.session.client_addr,
+                            protocol_label(&entry.session)
+                        ),
+                    )
+                    .changed();
+            }
+        });
+    drop(sessions);
+
+    if changed {
+        state.refilter();
+    }
 }
 
 fn draw_clear_button(state: &mut SharedState, ui: &mut Ui) {
     if ui.button("Clear").clicked() {
-        state.selected_packet = None;
-        state.packets.write().unwrap().clear();
+        state.clear_packets();
     }
 }
 
-fn draw_packet_list(state: &mut SharedState, ui: &mut Ui) {
-    let packets = state.packets.read().unwrap();
-    for (i, packet) in packets.iter().enumerate() {
-        if let Some(filtered) = state.packet_filter.get(packet) {
-            if !filtered {
-                continue;
+fn draw_capture_buttons(state: &mut SharedState, ui: &mut Ui) {
+    if ui.button("Save capture…").clicked() {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter(CAPTURE_FILTER_NAME, &[CAPTURE_EXTENSION])
+            .set_file_name(&format!("capture.{CAPTURE_EXTENSION}"))
+            .save_file()
+        {
+            if let Err(e) = state.save_capture(&path) {
+                tracing::error!("Failed to save capture: {:?}", e);
             }
         }
+    }
 
-        let selected = {
-            if let Some(selected) = state.selected_packet {
-                selected == i
-            } else {
-                false
+    // loading replaces the packet list, which would mix with live traffic
+    if ui
+        .add_enabled(!state.is_listening, egui::Button::new("Open capture…"))
+        .clicked()
+    {
+        if let Some(path) = rfd::FileDialog::new()
+            .add_filter(CAPTURE_FILTER_NAME, &[CAPTURE_EXTENSION])
+            .pick_file()
+        {
+            if let Err(e) = state.load_capture(&path) {
+                tracing::error!("Failed to open capture: {:?}", e);
             }
-        };
+        }
+    }
+}
+
+fn draw_packet_list(state: &mut SharedState, ui: &mut Ui, scroll_to: Option<usize>) {
+    let total_rows = state.visible.read().unwrap().len();
+    let mut clicked = None;
 
-        if draw_packet_widget(ui, packet, selected).clicked() {
-            state.selected_packet = Some(i);
+    let mut scroll_area = egui::ScrollArea::vertical()
+        .auto_shrink([false, false])
+        .stick_to_bottom(true);
+
+    if let Some(row) = scroll_to.and_then(|i| state.visible.read().unwrap().row_of(i)) {
+        // roughly in the middle of the list
+        let row_height = ROW_HEIGHT + ui.spacing().item_spacing.y;
+        let offset = row as f32 * row_height - ui.available_height() / 2.0;
+        scroll_area = scroll_area.vertical_scroll_offset(offset.max(0.0));
+    }
+
+    // only the rows in view are painted, captures get long
+    scroll_area.show_rows(ui, ROW_HEIGHT, total_rows, |ui, rows| {
+        let visible = state.visible.read().unwrap();
+        let search = state.search.read().unwrap();
+        let decode_errors = state.decode_errors.read().unwrap();
+        let trailing_bytes = state.trailing_bytes.read().unwrap();
+
+        for i in rows.filter_map(|row| visible.get(row)) {
+            let Some(packet) = state.packet(i) else {
+                continue;
+            };
+
+            let selected = state.selected_packet == Some(i);
+            let matched = search.is_match(i);
+
+            let issue = if decode_errors.contains(&i) {
+                Some(DecodeIssue::Failed)
+            } else {
+                trailing_bytes.get(&i).map(|n| DecodeIssue::Trailing(*n))
+            };
+
+            if draw_packet_widget(ui, &packet, selected, matched, issue).clicked() {
+                clicked = Some(i);
+            }
         }
+    });
+
+    if clicked.is_some() {
+        state.selected_packet = clicked;
     }
 }
 
-fn draw_packet_widget(ui: &mut Ui, packet: &Packet, selected: bool) -> Response {
+/// Trouble the decoder had with a packet, flagged at the end of its row.
+#[derive(Copy, Clone)]
+enum DecodeIssue {
+    Failed,
+    /// Number of bytes left over
+    Trailing(usize),
+}
+
+fn draw_packet_widget(
+    ui: &mut Ui,
+    packet: &Packet,
+    selected: bool,
+    matched: bool,
+    issue: Option<DecodeIssue>,
+) -> Response {
     let (mut rect, response) = ui.allocate_at_least(
         Vec2 {
             x: ui.available_width(),
-            y: 24.0,
+            y: ROW_HEIGHT,
         },
         Sense::click(),
     );
 
-    let fill = match selected /*packet.selected*/ {
-        true => Rgba::from_rgba_premultiplied(0.3, 0.3, 0.3, 0.4),
-        false => Rgba::from_rgba_premultiplied(0.0, 0.0, 0.0, 0.0),
+    let fill = match (selected, matched) {
+        (true, _) => Rgba::from_rgba_premultiplied(0.3, 0.3, 0.3, 0.4),
+        (false, true) => Rgba::from(SEARCH_COLOR).multiply(0.5),
+        (false, false) => Rgba::from_rgba_premultiplied(0.0, 0.0, 0.0, 0.0),
     };
 
     let text_color: Color32 = match selected /*packet.selected*/ {
@@ -160,7 +342,11 @@ fn draw_packet_widget(ui: &mut Ui, packet: &Packet, selected: bool) -> Response
         let label: WidgetText = packet.name.into();
         let label = label.into_galley(ui, Some(false), rect.width() - 60.0, TextStyle::Button);
 
-        let timestamp: WidgetText = systemtime_strftime(packet.timestamp.unwrap()).into();
+        // records of a capture may come without one
+        let timestamp: WidgetText = match packet.timestamp {
+            Some(timestamp) => systemtime_strftime(timestamp).into(),
+            None => "-".into(),
+        };
         let timestamp =
             timestamp.into_galley(ui, Some(false), rect.width() - 60.0, TextStyle::Button);
 
@@ -194,9 +380,31 @@ fn draw_packet_widget(ui: &mut Ui, packet: &Packet, selected: bool) -> Response
             },
             ui.visuals().weak_text_color(),
         );
+
+        if let Some(issue) = issue {
+            let warning: WidgetText = match issue {
+                DecodeIssue::Failed => "⚠".into(),
+                DecodeIssue::Trailing(n) => format!("+{}", n).into(),
+            };
+            let warning = warning.into_galley(ui, Some(false), 60.0, TextStyle::Button);
+            warning.paint_with_fallback_color(
+                ui.painter(),
+                Pos2 {
+                    x: rect.right() - warning.size().x - 4.0,
+                    y: rect.top() + 6.0,
+                },
+                ui.visuals().warn_fg_color,
+            );
+        }
     }
 
-    response
+    match issue {
+        Some(DecodeIssue::Failed) => response.on_hover_text("Failed to decode"),
+        Some(DecodeIssue::Trailing(n)) => {
+            response.on_hover_text(format!("{} bytes left over after decoding", n))
+        }
+        None => response,
+    }
 }
 
 fn get_triangle(direction: PacketSide, outer_rect: &Rect) -> PathShape {
@@ -1,49 +1,24 @@
-use super::{SharedState, Tab, View};
-
-mod utils {
-    use proxy_lib::Packet as ProxyPacket;
-    use proxy_lib::{PacketSide, PacketState};
-    use valence::protocol::{Decode, Packet};
-
-    use valence::advancement::packet::*;
-    use valence::client::action::*;
-    use valence::client::command::*;
-    use valence::client::custom_payload::*;
-    use valence::client::hand_swing::*;
-    use valence::client::interact_block::*;
-    use valence::client::interact_entity::*;
-    use valence::client::interact_item::*;
-    use valence::client::keepalive::*;
-    use valence::client::movement::*;
-    use valence::client::packet::structure_block::*;
-    use valence::client::packet::*;
-    use valence::client::resource_pack::*;
-    use valence::client::settings::*;
-    use valence::client::status::*;
-    use valence::client::teleport::*;
-    use valence::client::title::*;
-    use valence::entity::packet::*;
-    use valence::instance::packet::*;
-    use valence::inventory::packet::synchronize_recipes::*;
-    use valence::inventory::packet::*;
-    use valence::network::packet::*;
-    use valence::particle::*;
-    use valence::player_list::packet::*;
-    use valence::protocol::packet::boss_bar::*;
-    use valence::protocol::packet::chat::*;
-    use valence::protocol::packet::command::*;
-    use valence::protocol::packet::map::*;
-    use valence::protocol::packet::scoreboard::*;
-    use valence::protocol::packet::sound::*;
-    use valence::registry::tags::*;
-    use valence::world_border::packet::*;
-
-    include!(concat!(env!("OUT_DIR"), "/packet_to_string.rs"));
-}
+use std::ops::Range;
+
+use bytes::Bytes;
+use egui::RichText;
+use proxy_lib::{decode_packet, DecodeError, Field};
+
+use crate::search::Pattern;
+
+use super::{
+    hex_viewer::{field_color, format_hex, SEARCH_COLOR},
+    SharedState, Tab, View,
+};
 
 pub struct TextView {
     last_packet_id: Option<usize>,
     packet_str: String,
+    error: Option<DecodeError>,
+    /// Bytes the decoder left over
+    trailing: Bytes,
+    /// Show the decoder's `Debug` output instead of the field tree
+    raw: bool,
 }
 
 impl Tab for TextView {
@@ -51,6 +26,9 @@ impl Tab for TextView {
         Self {
             last_packet_id: None,
             packet_str: "".to_string(),
+            error: None,
+            trailing: Bytes::new(),
+            raw: false,
         }
     }
 
@@ -59,35 +37,207 @@ impl Tab for TextView {
     }
 }
 
+/// How the field tree and the Hex Viewer point at each other.
+struct FieldLinks {
+    highlight: Option<Range<usize>>,
+    hovered: Option<Range<usize>>,
+    clicked: Option<Range<usize>>,
+}
+
 impl View for TextView {
     fn ui(&mut self, ui: &mut egui::Ui, state: &mut SharedState) {
-        let packets = state.packets.read().unwrap();
+        let fields = state
+            .selected_fields()
+            .filter(|root| !root.children.is_empty());
+
         let Some(packet_index) = state.selected_packet else {
             self.last_packet_id = None;
             self.packet_str = "".to_string();
+            self.error = None;
+            self.trailing = Bytes::new();
             return;
         };
 
         if self.last_packet_id != Some(packet_index) {
             self.last_packet_id = Some(packet_index);
-            self.packet_str = utils::packet_to_string(&packets[packet_index]);
+            (self.packet_str, self.error, self.trailing) = match state.packet(packet_index) {
+                Some(packet) => match decode_packet(&packet) {
+                    Some(Ok(decoded)) => (decoded.text, None, decoded.trailing),
+                    Some(Err(e)) => (String::new(), Some(e), Bytes::new()),
+                    None => ("Not yet implemented".to_string(), None, Bytes::new()),
+                },
+                None => (
+                    "Dropped from the capture buffer".to_string(),
+                    None,
+                    Bytes::new(),
+                ),
+            };
+        }
+
+        if let Some(error) = &self.error {
+            state.hovered_field = None;
+            draw_decode_error(ui, error);
+            return;
+        }
+
+        if !self.trailing.is_empty() {
+            ui.colored_label(
+                ui.visuals().warn_fg_color,
+                format!(
+                    "⚠ {} trailing bytes not consumed by the decoder",
+                    self.trailing.len()
+                ),
+            )
+            .on_hover_text(format_hex(&self.trailing));
         }
 
-        code_view_ui(ui, &self.packet_str);
+        ui.horizontal(|ui| {
+            ui.radio_value(&mut self.raw, false, "Fields");
+            ui.radio_value(&mut self.raw, true, "Debug");
+        });
+
+        let pattern = state.search.read().unwrap().pattern().cloned();
+
+        let root = match &fields {
+            Some(root) if !self.raw => root,
+            _ => {
+                state.hovered_field = None;
+                let found = match &pattern {
+                    Some(pattern) => pattern.text_ranges(&self.packet_str),
+                    None => Vec::new(),
+                };
+                marked_code_view_ui(ui, &self.packet_str, &found);
+                return;
+            }
+        };
+
+        // the byte hovered in the Hex Viewer wins over the picked field
+        let highlight = state
+            .hovered_byte
+            .and_then(|offset| root.find_at(offset))
+            .filter(|field| !std::ptr::eq(*field, &**root))
+            .and_then(Field::range)
+            .or_else(|| state.selected_field.clone());
+
+        let mut links = FieldLinks {
+            highlight,
+            hovered: None,
+            clicked: None,
+        };
+
+        egui::ScrollArea::both()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                let id = ui.make_persistent_id("field_tree").with(packet_index);
+                for (i, field) in root.children.iter().enumerate() {
+                    draw_field(ui, field, id.with(i), true, pattern.as_ref(), &mut links);
+                }
+            });
+
+        state.hovered_field = links.hovered;
+        if let Some(range) = links.clicked {
+            state.selected_field = Some(range);
+        }
+    }
+}
+
+fn draw_decode_error(ui: &mut egui::Ui, error: &DecodeError) {
+    ui.colored_label(ui.visuals().error_fg_color, "⚠ Failed to decode");
+    ui.label(&error.message);
+    ui.label(format!(
+        "Stopped at byte {:#X}, {} bytes left",
+        error.offset,
+        error.remaining.len()
+    ));
+
+    if !error.remaining.is_empty() {
+        ui.separator();
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .show(ui, |ui| {
+                ui.monospace(format_hex(&error.remaining));
+            });
+    }
+}
+
+fn draw_field(
+    ui: &mut egui::Ui,
+    field: &Field,
+    id: egui::Id,
+    open: bool,
+    pattern: Option<&Pattern>,
+    links: &mut FieldLinks,
+) {
+    let range = field.range();
+    let position = match &range {
+        Some(range) => format!("  @{:#X}+{}", range.start, range.len()),
+        None => String::new(),
+    };
+
+    let text = if field.children.is_empty() {
+        format!("{}: {} = {}{}", field.name, field.ty, field.value, position)
+    } else {
+        format!("{}: {}{}", field.name, field.ty, position)
+    };
+
+    let found = pattern.map_or(false, |pattern| !pattern.text_ranges(&text).is_empty());
+
+    let mut text = RichText::new(text).color(field_color(field));
+    if range.is_some() && range == links.highlight {
+        text = text.background_color(ui.visuals().selection.bg_fill);
+    } else if found {
+        text = text.background_color(SEARCH_COLOR);
+    }
+
+    let response = if field.children.is_empty() {
+        ui.add(egui::Label::new(text).sense(egui::Sense::click()))
+            .context_menu(|ui| {
+                if ui.button("Copy value").clicked() {
+                    ui.output_mut(|o| o.copied_text = field.value.clone());
+                    ui.close_menu();
+                }
+                if ui.button("Copy name").clicked() {
+                    ui.output_mut(|o| o.copied_text = field.name.clone());
+                    ui.close_menu();
+                }
+            })
+    } else {
+        egui::CollapsingHeader::new(text)
+            .id_source(id)
+            .default_open(open)
+            .show(ui, |ui| {
+                for (i, child) in field.children.iter().enumerate() {
+                    draw_field(ui, child, id.with(i), false, pattern, links);
+                }
+            })
+            .header_response
+    };
+
+    if response.hovered() {
+        links.hovered = range.clone();
+    }
+    if response.clicked() {
+        links.clicked = range;
     }
 }
 
 // From: https://github.com/emilk/egui/blob/master/crates/egui_demo_lib/src/syntax_highlighting.rs
 
-use egui::text::LayoutJob;
+use egui::text::{LayoutJob, LayoutSection};
 
 /// View some code with syntax highlighting and selection.
-pub fn code_view_ui(ui: &mut egui::Ui, mut code: &str) {
+pub fn code_view_ui(ui: &mut egui::Ui, code: &str) {
+    marked_code_view_ui(ui, code, &[]);
+}
+
+/// Like [`code_view_ui`], with the byte ranges in `marks` standing out.
+fn marked_code_view_ui(ui: &mut egui::Ui, mut code: &str, marks: &[Range<usize>]) {
     let language = "rs";
     let theme = CodeTheme::from_memory(ui.ctx());
 
     let mut layouter = |ui: &egui::Ui, string: &str, wrap_width: f32| {
         let mut layout_job = highlight(ui.ctx(), &theme, string, language);
+        mark_ranges(&mut layout_job, marks, SEARCH_COLOR);
         layout_job.wrap.max_width = wrap_width; // no wrapping
         ui.fonts(|f| f.layout_job(layout_job))
     };
@@ -103,6 +253,48 @@ pub fn code_view_ui(ui: &mut egui::Ui, mut code: &str) {
     );
 }
 
+/// Gives the text in `ranges` a background, splitting sections where needed. `ranges` have to
+/// be sorted and must not overlap.
+fn mark_ranges(job: &mut LayoutJob, ranges: &[Range<usize>], color: egui::Color32) {
+    if ranges.is_empty() {
+        return;
+    }
+
+    let mut sections = Vec::with_capacity(job.sections.len() + ranges.len() * 2);
+    for section in job.sections.drain(..) {
+        let end = section.byte_range.end;
+        let mut start = section.byte_range.start;
+
+        for range in ranges.iter().filter(|r| r.start < end && r.end > start) {
+            let (from, to) = (range.start.max(start), range.end.min(end));
+            if from > start {
+                sections.push(LayoutSection {
+                    byte_range: start..from,
+                    ..section.clone()
+                });
+            }
+
+            let mut marked = LayoutSection {
+                byte_range: from..to,
+                ..section.clone()
+            };
+            marked.format.background = color;
+            sections.push(marked);
+
+            start = to;
+        }
+
+        if start < end {
+            sections.push(LayoutSection {
+                byte_range: start..end,
+                ..section
+            });
+        }
+    }
+
+    job.sections = sections;
+}
+
 /// Memoized Code highlighting
 pub fn highlight(ctx: &egui::Context, theme: &CodeTheme, code: &str, language: &str) -> LayoutJob {
     impl egui::util::cache::ComputerMut<(&CodeTheme, &str, &str), LayoutJob> for Highlighter {
@@ -307,7 +499,7 @@ impl Highlighter {
         let theme = theme.syntect_theme.syntect_key_name();
         let mut h = HighlightLines::new(syntax, &self.ts.themes[theme]);
 
-        use egui::text::{LayoutSection, TextFormat};
+        use egui::text::TextFormat;
 
         let mut job = LayoutJob {
             text: text.into(),
@@ -0,0 +1,187 @@
+use std::sync::{Arc, Mutex, RwLock};
+
+use bytes::Bytes;
+use egui::Context;
+use proxy_lib::{InterceptContext, Interceptor, PacketSide, PacketState, Session, Verdict};
+use tokio::sync::oneshot;
+use valence::protocol::decode::PacketFrame;
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct Breakpoint {
+    pub enabled: bool,
+    /// Either side when `None`
+    pub side: Option<PacketSide>,
+    /// Packet name, or id in decimal or `0x` hex. Ids only match in play, the same ids
+    /// are other packets in the other states. Matches nothing when empty.
+    pub target: String,
+}
+
+impl Breakpoint {
+    pub fn new() -> Self {
+        Self {
+            enabled: true,
+            side: None,
+            target: String::new(),
+        }
+    }
+
+    fn matches(&self, side: PacketSide, state: PacketState, name: &str, id: i32) -> bool {
+        if !self.enabled || self.side.map_or(false, |s| s != side) {
+            return false;
+        }
+
+        let target = self.target.trim();
+        if target.is_empty() {
+            return false;
+        }
+
+        let hex = target
+            .strip_prefix("0x")
+            .or_else(|| target.strip_prefix("0X"));
+        let parsed_id = match hex {
+            Some(hex) => i32::from_str_radix(hex, 16).ok(),
+            None => target.parse::<i32>().ok(),
+        };
+
+        match parsed_id {
+            Some(target_id) => state == PacketState::Play && target_id == id,
+            None => target.eq_ignore_ascii_case(name),
+        }
+    }
+}
+
+/// A packet held back by a breakpoint, waiting for the user to decide what happens to it.
+pub struct PausedPacket {
+    pub session: Arc<Session>,
+    pub side: PacketSide,
+    pub state: PacketState,
+    pub id: i32,
+    pub name: &'static str,
+    pub body: Bytes,
+    reply: oneshot::Sender<Verdict>,
+}
+
+impl PausedPacket {
+    pub fn resolve(self, verdict: Verdict) {
+        // the session may have closed in the meantime
+        let _ = self.reply.send(verdict);
+    }
+
+    /// The session went away and nobody is waiting for a verdict anymore.
+    pub fn is_abandoned(&self) -> bool {
+        self.reply.is_closed()
+    }
+}
+
+#[derive(Default, serde::Deserialize, serde::Serialize)]
+pub struct Breakpoints {
+    pub rules: RwLock<Vec<Breakpoint>>,
+    /// In the order they were hit
+    #[serde(skip)]
+    pub paused: Mutex<Vec<PausedPacket>>,
+}
+
+/// Pauses packets matching any of the breakpoints until they are resolved in the GUI.
+pub struct BreakpointInterceptor {
+    breakpoints: Arc<Breakpoints>,
+    egui_ctx: Option<Context>,
+}
+
+impl BreakpointInterceptor {
+    pub fn new(breakpoints: Arc<Breakpoints>, egui_ctx: Option<Context>) -> Self {
+        Self {
+            breakpoints,
+            egui_ctx,
+        }
+    }
+}
+
+impl Interceptor for BreakpointInterceptor {
+    fn intercept(&self, ctx: &InterceptContext, frame: &PacketFrame) -> Verdict {
+        let hit = self
+            .breakpoints
+            .rules
+            .read()
+            .unwrap()
+            .iter()
+            .any(|b| b.matches(ctx.side, ctx.state, ctx.name, frame.id));
+
+        if !hit {
+            return Verdict::Pass;
+        }
+
+        let (reply, receiver) = oneshot::channel();
+        self.breakpoints.paused.lock().unwrap().push(PausedPacket {
+            session: ctx.session.clone(),
+            side: ctx.side,
+            state: ctx.state,
+            id: frame.id,
+            name: ctx.name,
+            body: frame.body.clone().freeze(),
+            reply,
+        });
+
+        if let Some(egui_ctx) = &self.egui_ctx {
+            egui_ctx.request_repaint();
+        }
+
+        Verdict::Hold(receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breakpoint(target: &str) -> Breakpoint {
+        Breakpoint {
+            target: target.to_string(),
+            ..Breakpoint::new()
+        }
+    }
+
+    #[test]
+    fn ids_only_match_in_play() {
+        let b = breakpoint("0x02");
+
+        assert!(b.matches(PacketSide::Clientbound, PacketState::Play, "", 2));
+        assert!(!b.matches(PacketSide::Clientbound, PacketState::Login, "", 2));
+    }
+
+    #[test]
+    fn ids_in_any_notation() {
+        for target in ["0x1a", "0X1A", "26", " 26 "] {
+            assert!(
+                breakpoint(target).matches(PacketSide::Serverbound, PacketState::Play, "", 26),
+                "{target}"
+            );
+        }
+    }
+
+    #[test]
+    fn names_match_in_any_state() {
+        let b = breakpoint("loginsuccesss2c");
+
+        assert!(b.matches(
+            PacketSide::Clientbound,
+            PacketState::Login,
+            "LoginSuccessS2c",
+            2
+        ));
+        assert!(!b.matches(
+            PacketSide::Clientbound,
+            PacketState::Login,
+            "LoginHelloS2c",
+            1
+        ));
+    }
+
+    #[test]
+    fn side_and_empty_target_restrict() {
+        let mut b = breakpoint("26");
+        b.side = Some(PacketSide::Clientbound);
+
+        assert!(!b.matches(PacketSide::Serverbound, PacketState::Play, "", 26));
+        assert!(!breakpoint(" ").matches(PacketSide::Serverbound, PacketState::Play, "", 26));
+    }
+}
@@ -10,6 +10,9 @@
 mod tri_checkbox;
 
 mod app;
+mod breakpoints;
+mod packet_buffer;
+mod search;
 mod shared_state;
 
 #[tokio::main]
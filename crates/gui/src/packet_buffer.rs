@@ -0,0 +1,504 @@
+//! Packets the GUI holds on to, limited so long sessions don't take up all memory.
+
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+use anyhow::bail;
+use proxy_lib::{CaptureReader, CaptureWriter, Packet, ProxyEvent};
+
+/// Spilled packets are read back this many at a time
+const PAGE_LEN: usize = 256;
+/// Pages of spilled packets kept around after reading them back
+const CACHED_PAGES: usize = 4;
+
+/// What to do once a capture gets long.
+#[derive(Clone, Copy, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub enum Retention {
+    /// Keep every packet in memory
+    #[default]
+    Unbounded,
+    /// Drop the oldest packets beyond this many
+    Packets(usize),
+    /// Drop the oldest packets once their bodies take up more than this many megabytes
+    Megabytes(usize),
+    /// Move the oldest packets beyond this many to a capture file on disk
+    Spill(usize),
+}
+
+/// Packets in the order they were pushed. A packet keeps its index for as long as it is around,
+/// the indices of dropped packets are not reused until the buffer is cleared.
+///
+/// Indices below [`first_index`](Self::first_index) belong to dropped packets, then come the
+/// spilled ones and then the ones in memory.
+#[derive(Default)]
+pub struct PacketBuffer {
+    retention: Retention,
+    dropped: usize,
+    spill: Option<Spill>,
+    packets: VecDeque<Packet>,
+    /// Body bytes of `packets`
+    bytes: usize,
+}
+
+impl PacketBuffer {
+    pub fn new(retention: Retention) -> Self {
+        Self {
+            retention,
+            ..Self::default()
+        }
+    }
+
+    pub fn retention(&self) -> Retention {
+        self.retention
+    }
+
+    /// Applies `retention` to the packets already in the buffer too. Packets on disk are dropped
+    /// when no longer spilling.
+    ///
+    /// Falls back to keeping as many packets in memory if spilling fails.
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+        if !matches!(retention, Retention::Spill(_)) {
+            self.drop_spill();
+        }
+        self.evict();
+    }
+
+    /// Number of packets ever pushed, the index the next one gets.
+    pub fn len(&self) -> usize {
+        self.dropped + self.spilled() + self.packets.len()
+    }
+
+    /// Index of the oldest packet still around.
+    pub fn first_index(&self) -> usize {
+        self.dropped
+    }
+
+    /// Packets that are gone for good.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Packets that were moved to disk.
+    pub fn spilled(&self) -> usize {
+        self.spill.as_ref().map_or(0, |spill| spill.offsets.len())
+    }
+
+    /// Packets held in memory, and the size of their bodies.
+    pub fn in_memory(&self) -> (usize, usize) {
+        (self.packets.len(), self.bytes)
+    }
+
+    pub fn push(&mut self, packet: Packet) {
+        self.bytes += body_len(&packet);
+        self.packets.push_back(packet);
+        self.evict();
+    }
+
+    /// The packet at `index`, read back from disk if it was spilled. `None` once it was dropped.
+    pub fn get(&self, index: usize) -> Option<Packet> {
+        let row = index.checked_sub(self.dropped)?;
+
+        match &self.spill {
+            Some(spill) if row < spill.offsets.len() => match spill.read(row) {
+                Ok(packet) => Some(packet),
+                Err(e) => {
+                    tracing::error!("Failed to read back spilled packet: {:?}", e);
+                    None
+                }
+            },
+            _ => self.packets.get(row - self.spilled()).cloned(),
+        }
+    }
+
+    /// Calls `f` with every packet still around and its index, oldest first.
+    pub fn try_for_each(
+        &self,
+        mut f: impl FnMut(usize, &Packet) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if let Some(spill) = &self.spill {
+            spill.try_for_each(|row, packet| f(self.dropped + row, packet))?;
+        }
+
+        let start = self.dropped + self.spilled();
+        for (i, packet) in self.packets.iter().enumerate() {
+            f(start + i, packet)?;
+        }
+
+        Ok(())
+    }
+
+    /// The packets held in memory, spilled ones are left as they are.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Packet> {
+        self.packets.iter_mut()
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new(self.retention);
+    }
+
+    fn evict(&mut self) {
+        loop {
+            let over = match self.retention {
+                Retention::Unbounded => false,
+                Retention::Packets(n) | Retention::Spill(n) => self.packets.len() > n,
+                Retention::Megabytes(mb) => self.bytes > mb.saturating_mul(1024 * 1024),
+            };
+
+            if !over {
+                break;
+            }
+
+            let Some(packet) = self.packets.pop_front() else {
+                break;
+            };
+            self.bytes -= body_len(&packet);
+
+            let Retention::Spill(n) = self.retention else {
+                self.dropped += 1;
+                continue;
+            };
+
+            if let Err(e) = self.spill_packet(&packet) {
+                // rather than failing again for every packet that follows
+                tracing::error!("Failed to spill packets to disk, dropping them: {:?}", e);
+                self.retention = Retention::Packets(n);
+                self.drop_spill();
+                self.dropped += 1;
+            }
+        }
+    }
+
+    fn spill_packet(&mut self, packet: &Packet) -> anyhow::Result<()> {
+        if self.spill.is_none() {
+            self.spill = Some(Spill::create()?);
+        }
+
+        self.spill.as_mut().unwrap().write(packet)
+    }
+
+    fn drop_spill(&mut self) {
+        if let Some(spill) = self.spill.take() {
+            self.dropped += spill.offsets.len();
+        }
+    }
+}
+
+fn body_len(packet: &Packet) -> usize {
+    packet.data.as_ref().map_or(0, |data| data.len())
+}
+
+/// Packets moved out of memory, kept in a capture file that is deleted along with the spill.
+struct Spill {
+    path: PathBuf,
+    /// Where the records of the spilled packets start
+    offsets: Vec<u64>,
+    /// Locked so packets can be read back through a shared reference
+    file: Mutex<SpillFile>,
+}
+
+struct SpillFile {
+    writer: CaptureWriter<BufWriter<File>>,
+    /// The writer has records that aren't on disk yet
+    dirty: bool,
+    reader: CaptureReader<BufReader<File>>,
+    pages: VecDeque<(usize, Vec<Packet>)>,
+}
+
+impl Spill {
+    fn create() -> anyhow::Result<Self> {
+        static SPILLS: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "packet-inspector-{}-{}.picf",
+            std::process::id(),
+            SPILLS.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let mut writer = CaptureWriter::new(BufWriter::new(File::create(&path)?))?;
+        writer.flush()?;
+        let reader = CaptureReader::new(BufReader::new(File::open(&path)?))?;
+
+        Ok(Self {
+            path,
+            offsets: Vec::new(),
+            file: Mutex::new(SpillFile {
+                writer,
+                dirty: false,
+                reader,
+                pages: VecDeque::new(),
+            }),
+        })
+    }
+
+    fn write(&mut self, packet: &Packet) -> anyhow::Result<()> {
+        let row = self.offsets.len();
+        let file = self.file.get_mut().unwrap();
+
+        let offset = file.writer.position();
+        file.writer.write_packet(packet)?;
+        file.dirty = true;
+
+        // the reader has to know the session to read the packet back
+        if let Some(session) = &packet.session {
+            file.reader.add_session(session.clone());
+        }
+
+        // the page this lands in may have been read back while it was shorter
+        file.pages.retain(|(page, _)| *page != row / PAGE_LEN);
+
+        self.offsets.push(offset);
+        Ok(())
+    }
+
+    fn read(&self, row: usize) -> anyhow::Result<Packet> {
+        let page = row / PAGE_LEN;
+        let mut file = self.file.lock().unwrap();
+
+        if let Some((_, packets)) = file.pages.iter().find(|(p, _)| *p == page) {
+            return Ok(packets[row % PAGE_LEN].clone());
+        }
+
+        let start = page * PAGE_LEN;
+        let len = PAGE_LEN.min(self.offsets.len() - start);
+        let mut packets = Vec::with_capacity(len);
+        file.read_packets(self.offsets[start], len, |packet| {
+            packets.push(packet.clone());
+            Ok(())
+        })?;
+
+        let packet = packets[row % PAGE_LEN].clone();
+        file.pages.push_back((page, packets));
+        if file.pages.len() > CACHED_PAGES {
+            file.pages.pop_front();
+        }
+
+        Ok(packet)
+    }
+
+    fn try_for_each(
+        &self,
+        mut f: impl FnMut(usize, &Packet) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let Some(&offset) = self.offsets.first() else {
+            return Ok(());
+        };
+
+        let mut row = 0;
+        self.file
+            .lock()
+            .unwrap()
+            .read_packets(offset, self.offsets.len(), |packet| {
+                f(row, packet)?;
+                row += 1;
+                Ok(())
+            })
+    }
+}
+
+impl SpillFile {
+    /// Reads `count` packets starting at the record at `offset`.
+    fn read_packets(
+        &mut self,
+        offset: u64,
+        count: usize,
+        mut f: impl FnMut(&Packet) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        if self.dirty {
+            self.writer.flush()?;
+            self.dirty = false;
+        }
+
+        self.reader.seek(offset)?;
+
+        let mut read = 0;
+        while read < count {
+            match self.reader.read_event()? {
+                Some(ProxyEvent::Packet(packet)) => {
+                    f(&packet)?;
+                    read += 1;
+                }
+                // opened records of the sessions are written along with their first packet
+                Some(_) => {}
+                None => bail!("spill file ends after {} of {} packets", read, count),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            tracing::warn!("Failed to remove {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use proxy_lib::{PacketSide, PacketState};
+
+    use super::*;
+
+    fn packet(id: i32, len: usize) -> Packet {
+        Packet {
+            side: PacketSide::Clientbound,
+            state: PacketState::Play,
+            id,
+            timestamp: None,
+            name: "",
+            data: Some(Bytes::from(vec![id as u8; len])),
+            session: None,
+            schema: None,
+        }
+    }
+
+    fn filled(retention: Retention, count: i32) -> PacketBuffer {
+        let mut buffer = PacketBuffer::new(retention);
+        for id in 0..count {
+            buffer.push(packet(id, 8));
+        }
+        buffer
+    }
+
+    /// Index and id of every packet still around.
+    fn ids(buffer: &PacketBuffer) -> Vec<(usize, i32)> {
+        let mut ids = Vec::new();
+        buffer
+            .try_for_each(|i, packet| {
+                ids.push((i, packet.id));
+                Ok(())
+            })
+            .unwrap();
+        ids
+    }
+
+    #[test]
+    fn unbounded_keeps_everything() {
+        let buffer = filled(Retention::Unbounded, 10);
+
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(buffer.first_index(), 0);
+        assert_eq!(buffer.in_memory(), (10, 80));
+        assert_eq!(buffer.get(9), Some(packet(9, 8)));
+        assert_eq!(buffer.get(10), None);
+    }
+
+    #[test]
+    fn packets_keeps_indices_across_evictions() {
+        let mut buffer = filled(Retention::Packets(3), 5);
+
+        assert_eq!(buffer.len(), 5);
+        assert_eq!(buffer.first_index(), 2);
+        assert_eq!(buffer.dropped(), 2);
+        assert_eq!(buffer.get(1), None);
+        assert_eq!(buffer.get(2), Some(packet(2, 8)));
+        assert_eq!(ids(&buffer), [(2, 2), (3, 3), (4, 4)]);
+
+        buffer.push(packet(5, 8));
+        assert_eq!(buffer.first_index(), 3);
+        assert_eq!(buffer.get(2), None);
+        assert_eq!(buffer.get(4), Some(packet(4, 8)));
+        assert_eq!(buffer.get(5), Some(packet(5, 8)));
+    }
+
+    #[test]
+    fn megabytes_drops_by_body_size() {
+        let mut buffer = PacketBuffer::new(Retention::Megabytes(1));
+        for id in 0..4 {
+            buffer.push(packet(id, 400 * 1024));
+        }
+
+        assert_eq!(buffer.first_index(), 2);
+        assert_eq!(buffer.in_memory(), (2, 800 * 1024));
+        assert_eq!(buffer.get(1), None);
+        assert_eq!(buffer.get(3), Some(packet(3, 400 * 1024)));
+    }
+
+    #[test]
+    fn spilled_packets_read_back_equal() {
+        let buffer = filled(Retention::Spill(3), 10);
+
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(buffer.first_index(), 0);
+        assert_eq!(buffer.spilled(), 7);
+        assert_eq!(buffer.in_memory(), (3, 24));
+        for id in 0..10 {
+            assert_eq!(buffer.get(id as usize), Some(packet(id, 8)));
+        }
+        assert_eq!(
+            ids(&buffer),
+            (0..10).map(|id| (id as usize, id)).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn spill_pages_see_later_writes() {
+        let mut buffer = filled(Retention::Spill(0), 10);
+        // caches the first page while it is still short
+        assert_eq!(buffer.get(9), Some(packet(9, 8)));
+
+        let count = (PAGE_LEN * (CACHED_PAGES + 2)) as i32;
+        for id in 10..count {
+            buffer.push(packet(id, 8));
+        }
+
+        // through more pages than are cached, both ways
+        let len = buffer.len();
+        assert_eq!(len, count as usize);
+        for index in (0..len).chain((0..len).rev()) {
+            assert_eq!(buffer.get(index), Some(packet(index as i32, 8)));
+        }
+    }
+
+    #[test]
+    fn leaving_spill_drops_spilled_packets() {
+        let mut buffer = filled(Retention::Spill(3), 10);
+        let path = buffer.spill.as_ref().unwrap().path.clone();
+
+        buffer.set_retention(Retention::Packets(5));
+
+        assert_eq!(buffer.len(), 10);
+        assert_eq!(buffer.first_index(), 7);
+        assert_eq!(buffer.spilled(), 0);
+        assert_eq!(buffer.get(6), None);
+        assert_eq!(buffer.get(7), Some(packet(7, 8)));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn clear_after_spill_starts_over() {
+        let mut buffer = filled(Retention::Spill(3), 10);
+        let path = buffer.spill.as_ref().unwrap().path.clone();
+
+        buffer.clear();
+
+        assert_eq!(buffer.len(), 0);
+        assert_eq!(buffer.first_index(), 0);
+        assert_eq!(buffer.spilled(), 0);
+        assert_eq!(buffer.retention(), Retention::Spill(3));
+        assert_eq!(buffer.get(0), None);
+        assert!(!path.exists());
+
+        for id in 100..105 {
+            buffer.push(packet(id, 8));
+        }
+        assert_eq!(buffer.spilled(), 2);
+        assert_eq!(buffer.get(0), Some(packet(100, 8)));
+        assert_eq!(
+            ids(&buffer),
+            [(0, 100), (1, 101), (2, 102), (3, 103), (4, 104)]
+        );
+    }
+}
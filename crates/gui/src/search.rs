@@ -0,0 +1,288 @@
+//! Finding packets by name, body bytes or decoded text.
+
+use std::{collections::VecDeque, ops::Range};
+
+use proxy_lib::Packet;
+
+/// What to look for.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Pattern {
+    /// Found in names and decoded text ignoring ASCII case, and as UTF-8 in bodies as is
+    Text(String),
+    /// Found in bodies
+    Bytes(Vec<u8>),
+}
+
+impl Pattern {
+    /// Whether `packet` matches. `decode` gives its decoded text, it is only called when the
+    /// name and body don't match already.
+    pub fn matches(&self, packet: &Packet, decode: impl FnOnce() -> Option<String>) -> bool {
+        let body = packet.data.as_deref().unwrap_or_default();
+
+        match self {
+            Self::Text(text) => {
+                find_text(packet.name, text).is_some()
+                    || find_bytes(body, text.as_bytes()).is_some()
+                    || decode().is_some_and(|decoded| find_text(&decoded, text).is_some())
+            }
+            Self::Bytes(bytes) => find_bytes(body, bytes).is_some(),
+        }
+    }
+
+    /// Ranges of `body` the pattern was found at.
+    pub fn body_ranges(&self, body: &[u8]) -> Vec<Range<usize>> {
+        let needle = match self {
+            Self::Text(text) => text.as_bytes(),
+            Self::Bytes(bytes) => bytes,
+        };
+
+        all_ranges(body, needle.len(), |haystack| find_bytes(haystack, needle))
+    }
+
+    /// Ranges of `text` the pattern was found at, none for byte patterns.
+    pub fn text_ranges(&self, text: &str) -> Vec<Range<usize>> {
+        match self {
+            Self::Text(needle) => all_ranges(text.as_bytes(), needle.len(), |haystack| {
+                find_bytes_ignore_case(haystack, needle.as_bytes())
+            }),
+            Self::Bytes(_) => Vec::new(),
+        }
+    }
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Lowercasing only ASCII keeps byte offsets the same.
+fn find_bytes_ignore_case(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return None;
+    }
+
+    haystack
+        .windows(needle.len())
+        .position(|w| w.eq_ignore_ascii_case(needle))
+}
+
+fn find_text(haystack: &str, needle: &str) -> Option<usize> {
+    find_bytes_ignore_case(haystack.as_bytes(), needle.as_bytes())
+}
+
+fn all_ranges(
+    haystack: &[u8],
+    len: usize,
+    find: impl Fn(&[u8]) -> Option<usize>,
+) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+
+    while let Some(i) = find(&haystack[start..]) {
+        ranges.push(start + i..start + i + len);
+        start += i + len;
+    }
+
+    ranges
+}
+
+/// The current search and the packets it found, kept up to date as packets arrive like
+/// `VisiblePackets`.
+#[derive(Default)]
+pub struct Search {
+    pattern: Option<Pattern>,
+    /// Indices of the matching visible packets, in capture order
+    matches: VecDeque<usize>,
+}
+
+impl Search {
+    /// See `SharedState::search`
+    pub fn new(pattern: Option<Pattern>, matches: VecDeque<usize>) -> Self {
+        Self { pattern, matches }
+    }
+
+    pub fn pattern(&self) -> Option<&Pattern> {
+        self.pattern.as_ref()
+    }
+
+    pub fn push(&mut self, index: usize) {
+        self.matches.push_back(index);
+    }
+
+    /// Forgets the matches but not the pattern, for when the packets are gone.
+    pub fn clear(&mut self) {
+        self.matches.clear();
+    }
+
+    pub fn forget_before(&mut self, first: usize) {
+        while self.matches.front().is_some_and(|&i| i < first) {
+            self.matches.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn is_match(&self, index: usize) -> bool {
+        self.matches.binary_search(&index).is_ok()
+    }
+
+    /// Position of the packet at `index` among the matches, counting from 1.
+    pub fn position(&self, index: usize) -> Option<usize> {
+        self.matches.binary_search(&index).ok().map(|i| i + 1)
+    }
+
+    /// The first match after the packet at `index`, wrapping around to the first one.
+    pub fn next(&self, index: Option<usize>) -> Option<usize> {
+        let row = index.map_or(0, |index| self.matches.partition_point(|&i| i <= index));
+        self.matches
+            .get(row)
+            .or_else(|| self.matches.front())
+            .copied()
+    }
+
+    /// The last match before the packet at `index`, wrapping around to the last one.
+    pub fn previous(&self, index: Option<usize>) -> Option<usize> {
+        let row = index.map_or(0, |index| self.matches.partition_point(|&i| i < index));
+        row.checked_sub(1)
+            .and_then(|row| self.matches.get(row))
+            .or_else(|| self.matches.back())
+            .copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use proxy_lib::{PacketSide, PacketState};
+
+    use super::*;
+
+    fn packet(name: &'static str, body: &[u8]) -> Packet {
+        Packet {
+            side: PacketSide::Clientbound,
+            state: PacketState::Play,
+            id: 0,
+            timestamp: None,
+            name,
+            data: Some(Bytes::copy_from_slice(body)),
+            session: None,
+            schema: None,
+        }
+    }
+
+    fn text(text: &str) -> Pattern {
+        Pattern::Text(text.to_string())
+    }
+
+    fn search(matches: &[usize]) -> Search {
+        Search::new(Some(text("x")), matches.iter().copied().collect())
+    }
+
+    #[test]
+    fn text_matches_name_ignoring_case() {
+        let p = packet("ChatMessageS2c", b"");
+
+        assert!(text("chatmessage").matches(&p, || None));
+        assert!(!text("title").matches(&p, || None));
+    }
+
+    #[test]
+    fn text_matches_body_with_case() {
+        let p = packet("ChatMessageS2c", b"Hello");
+
+        assert!(text("Hello").matches(&p, || None));
+        assert!(!text("hello").matches(&p, || None));
+        assert_eq!(text("hello").body_ranges(b"Hello"), []);
+    }
+
+    #[test]
+    fn text_matches_decoded_ignoring_case() {
+        let p = packet("ChatMessageS2c", b"");
+
+        assert!(text("hello").matches(&p, || Some("content: \"Hello\"".to_string())));
+        assert_eq!(text("hello").text_ranges("Hello hello"), [0..5, 6..11]);
+    }
+
+    #[test]
+    fn decode_only_runs_without_other_match() {
+        let p = packet("ChatMessageS2c", b"Hello");
+
+        assert!(text("Chat").matches(&p, || unreachable!()));
+        assert!(text("Hello").matches(&p, || unreachable!()));
+    }
+
+    #[test]
+    fn bytes_match_body_only() {
+        let p = packet("ChatMessageS2c", &[0x00, 0xff, 0x10]);
+
+        assert!(Pattern::Bytes(vec![0xff, 0x10]).matches(&p, || None));
+        assert!(!Pattern::Bytes(b"Chat".to_vec()).matches(&p, || Some("Chat".to_string())));
+        assert_eq!(Pattern::Bytes(vec![0xff]).text_ranges("\u{ff}"), []);
+    }
+
+    #[test]
+    fn empty_needle_matches_nothing() {
+        let p = packet("ChatMessageS2c", b"Hello");
+
+        assert!(!text("").matches(&p, || Some("Hello".to_string())));
+        assert!(!Pattern::Bytes(Vec::new()).matches(&p, || None));
+        assert_eq!(text("").body_ranges(b"Hello"), []);
+        assert_eq!(text("").text_ranges("Hello"), []);
+    }
+
+    #[test]
+    fn overlapping_matches_count_once() {
+        assert_eq!(
+            Pattern::Bytes(vec![1, 1]).body_ranges(&[1, 1, 1, 1, 1]),
+            [0..2, 2..4]
+        );
+        assert_eq!(
+            all_ranges(b"abab", 2, |h| find_bytes(h, b"ab")),
+            [0..2, 2..4]
+        );
+    }
+
+    #[test]
+    fn next_wraps_around() {
+        let s = search(&[3, 7, 9]);
+
+        assert_eq!(s.next(None), Some(3));
+        assert_eq!(s.next(Some(3)), Some(7));
+        assert_eq!(s.next(Some(5)), Some(7));
+        assert_eq!(s.next(Some(9)), Some(3));
+        assert_eq!(s.next(Some(12)), Some(3));
+        assert_eq!(search(&[]).next(Some(1)), None);
+    }
+
+    #[test]
+    fn previous_wraps_around() {
+        let s = search(&[3, 7, 9]);
+
+        assert_eq!(s.previous(None), Some(9));
+        assert_eq!(s.previous(Some(9)), Some(7));
+        assert_eq!(s.previous(Some(8)), Some(7));
+        assert_eq!(s.previous(Some(3)), Some(9));
+        assert_eq!(s.previous(Some(1)), Some(9));
+        assert_eq!(search(&[]).previous(Some(1)), None);
+    }
+
+    #[test]
+    fn forget_before_drops_evicted_matches() {
+        let mut s = search(&[3, 7, 9]);
+        s.forget_before(7);
+
+        assert_eq!(s.len(), 2);
+        assert!(!s.is_match(3));
+        assert_eq!(s.position(7), Some(1));
+        assert_eq!(s.next(Some(9)), Some(7));
+
+        s.forget_before(10);
+        assert_eq!(s.len(), 0);
+        assert_eq!(s.pattern(), Some(&text("x")));
+    }
+}
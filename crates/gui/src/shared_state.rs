@@ -1,8 +1,25 @@
 #![allow(clippy::mutable_key_type)]
 
 use egui::Context;
-use proxy_lib::Packet;
-use std::{collections::HashMap, sync::RwLock};
+use proxy_lib::{
+    decode_fields, decode_packet, CaptureReader, CaptureWriter, CloseReason, DisplayFilter, Field,
+    Injector, Packet, PacketDefinitions, PacketSide, PacketState, ProxyEvent, Session, SessionId,
+    ShutdownHandle, STD_PROTOCOL_VERSION,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    fs::File,
+    io::{BufReader, BufWriter},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use crate::{
+    breakpoints::Breakpoints,
+    packet_buffer::{PacketBuffer, Retention},
+    search::{Pattern, Search},
+};
 
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct PacketFilter {
@@ -46,9 +63,86 @@ impl PacketFilter {
     }
 }
 
+pub struct SessionEntry {
+    pub session: Arc<Session>,
+    /// `None` while the connection is still open
+    pub closed: Option<CloseReason>,
+    /// Per packet type, how often decoding left bytes over
+    pub trailing: HashMap<(PacketSide, PacketState, i32), TrailingStats>,
+}
+
+pub struct TrailingStats {
+    pub name: &'static str,
+    /// Packets of this type that decoded successfully
+    pub decoded: u64,
+    /// How many of those had trailing bytes
+    pub with_trailing: u64,
+    pub max_trailing: usize,
+}
+
+impl TrailingStats {
+    /// Every packet of this type had bytes left over, which points at a definition mismatch
+    /// rather than a one-off malformed packet.
+    pub fn is_consistent(&self) -> bool {
+        self.with_trailing > 0 && self.with_trailing == self.decoded
+    }
+}
+
+/// Indices of the packets that pass the filters, in capture order. Kept up to date as packets
+/// arrive, so the packet list only has to look at the rows it shows.
+#[derive(Default)]
+pub struct VisiblePackets {
+    indices: VecDeque<usize>,
+}
+
+impl VisiblePackets {
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Index of the packet shown in `row`
+    pub fn get(&self, row: usize) -> Option<usize> {
+        self.indices.get(row).copied()
+    }
+
+    pub fn first(&self) -> Option<usize> {
+        self.indices.front().copied()
+    }
+
+    /// The closest visible packet before the packet at `index`
+    pub fn previous(&self, index: usize) -> Option<usize> {
+        let row = self.indices.partition_point(|&i| i < index);
+        row.checked_sub(1).map(|row| self.indices[row])
+    }
+
+    /// The closest visible packet after the packet at `index`
+    pub fn next(&self, index: usize) -> Option<usize> {
+        let row = self.indices.partition_point(|&i| i <= index);
+        self.get(row)
+    }
+
+    /// Row showing the packet at `index`
+    pub fn row_of(&self, index: usize) -> Option<usize> {
+        self.indices.binary_search(&index).ok()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.indices.iter().copied()
+    }
+
+    fn forget_before(&mut self, first: usize) {
+        while self.indices.front().is_some_and(|&i| i < first) {
+            self.indices.pop_front();
+        }
+    }
+}
+
 pub enum Event {
     StartListening,
     StopListening,
+    /// Re-emit a capture file at its original pace, scaled by `SharedState::replay_speed`
+    StartReplay(PathBuf),
+    StopReplay,
     PacketReceived,
 }
 
@@ -56,17 +150,82 @@ pub enum Event {
 pub struct SharedState {
     pub listener_addr: String,
     pub server_addr: String,
+    /// Decrypt online-mode logins by terminating encryption at the proxy
+    #[serde(default)]
+    pub online_mode: bool,
     #[serde(skip)]
     pub is_listening: bool,
+    /// Sends packets into the sessions of the running proxy
+    #[serde(skip)]
+    pub injector: Option<Injector>,
+    /// Stops the running proxy
+    #[serde(skip)]
+    pub shutdown: Option<ShutdownHandle>,
+    #[serde(default = "default_replay_speed")]
+    pub replay_speed: f32,
+    #[serde(skip)]
+    pub is_replaying: bool,
 
     pub packet_filter: PacketFilter,
+    /// Packet definitions file, loaded again on start
+    #[serde(default)]
+    pub definitions_path: Option<PathBuf>,
+    /// Protocol version the next loaded definitions apply to
+    #[serde(default = "default_definitions_protocol")]
+    pub definitions_protocol: i32,
+    #[serde(skip)]
+    pub definitions: Option<Arc<PacketDefinitions>>,
+    /// Only show packets that failed to decode
+    #[serde(default)]
+    pub decode_errors_only: bool,
+    /// Text of `display_filter`, to parse it again on the next start
+    #[serde(default)]
+    display_filter_source: String,
+    /// See `SharedState::set_display_filter`
+    #[serde(skip)]
+    display_filter: Option<DisplayFilter>,
+    #[serde(default)]
+    pub breakpoints: Arc<Breakpoints>,
+    /// See `SharedState::set_retention`
+    #[serde(default)]
+    pub retention: Retention,
 
     // pub listener_addr: String,
     // pub server_addr: String,
     #[serde(skip)]
     pub selected_packet: Option<usize>,
+    /// Decoded fields of the selected packet, see `SharedState::selected_fields`
+    #[serde(skip)]
+    decoded: Option<(usize, Option<Arc<Field>>)>,
+    /// Byte range of the field picked in the Text Viewer or Hex Viewer
+    #[serde(skip)]
+    pub selected_field: Option<Range<usize>>,
+    /// Byte range of the field under the cursor in the Text Viewer
+    #[serde(skip)]
+    pub hovered_field: Option<Range<usize>>,
+    /// Byte under the cursor in the Hex Viewer
+    #[serde(skip)]
+    pub hovered_byte: Option<usize>,
+    /// Use `SharedState::packet` to get at a single packet
+    #[serde(skip)]
+    pub packets: RwLock<PacketBuffer>,
+    /// See `SharedState::refilter`
+    #[serde(skip)]
+    pub visible: RwLock<VisiblePackets>,
+    /// See `SharedState::search`
     #[serde(skip)]
-    pub packets: RwLock<Vec<Packet>>,
+    pub search: RwLock<Search>,
+    /// Indices of the packets that failed to decode
+    #[serde(skip)]
+    pub decode_errors: RwLock<BTreeSet<usize>>,
+    /// Number of bytes the decoder left over, by packet index
+    #[serde(skip)]
+    pub trailing_bytes: RwLock<BTreeMap<usize, usize>>,
+    #[serde(skip)]
+    pub sessions: RwLock<Vec<SessionEntry>>,
+    /// Only show packets of this session, all sessions when `None`
+    #[serde(skip)]
+    pub session_filter: Option<SessionId>,
     #[serde(skip)]
     pub(super) receiver: Option<flume::Receiver<Event>>,
     #[serde(skip)]
@@ -75,6 +234,14 @@ pub struct SharedState {
     pub ctx: Option<Context>,
 }
 
+fn default_replay_speed() -> f32 {
+    1.0
+}
+
+fn default_definitions_protocol() -> i32 {
+    STD_PROTOCOL_VERSION
+}
+
 impl Default for SharedState {
     fn default() -> Self {
         let (sender, receiver) = flume::unbounded();
@@ -82,10 +249,33 @@ impl Default for SharedState {
         Self {
             listener_addr: "127.0.0.1:25566".to_string(),
             server_addr: "127.0.0.1:25565".to_string(),
+            online_mode: false,
             is_listening: false,
+            injector: None,
+            shutdown: None,
+            replay_speed: default_replay_speed(),
+            is_replaying: false,
             packet_filter: PacketFilter::new(),
+            definitions_path: None,
+            definitions_protocol: default_definitions_protocol(),
+            definitions: None,
+            decode_errors_only: false,
+            display_filter_source: String::new(),
+            display_filter: None,
+            breakpoints: Arc::default(),
+            retention: Retention::default(),
             selected_packet: None,
-            packets: RwLock::new(Vec::new()),
+            decoded: None,
+            selected_field: None,
+            hovered_field: None,
+            hovered_byte: None,
+            packets: RwLock::default(),
+            visible: RwLock::default(),
+            search: RwLock::default(),
+            decode_errors: RwLock::default(),
+            trailing_bytes: RwLock::default(),
+            sessions: RwLock::new(Vec::new()),
+            session_filter: None,
             receiver: Some(receiver),
             sender: Some(sender),
             ctx: None,
@@ -122,13 +312,374 @@ impl SharedState {
         }
 
         self.packet_filter = packet_filter;
+        self.packets = RwLock::new(PacketBuffer::new(self.retention));
+
+        if !self.display_filter_source.is_empty() {
+            match DisplayFilter::parse(&self.display_filter_source) {
+                Ok(filter) => self.display_filter = Some(filter),
+                Err(e) => tracing::error!("Failed to parse the saved display filter: {:#}", e),
+            }
+        }
+
+        if let Some(path) = self.definitions_path.clone() {
+            if let Err(e) = self.load_definitions(&path) {
+                tracing::error!("Failed to load packet definitions: {:?}", e);
+            }
+        }
 
         self
     }
 
+    pub fn is_packet_visible(&self, index: usize, packet: &Packet) -> bool {
+        if self.decode_errors_only && !self.decode_errors.read().unwrap().contains(&index) {
+            return false;
+        }
+
+        if let Some(session_id) = self.session_filter {
+            match &packet.session {
+                Some(session) if session.id == session_id => {}
+                _ => return false,
+            }
+        }
+
+        if !self.packet_filter.get(packet).unwrap_or(true) {
+            return false;
+        }
+
+        let Some(filter) = &self.display_filter else {
+            return true;
+        };
+
+        // spilled packets come back with the names of the bundled tables
+        let defined = self
+            .definitions
+            .as_deref()
+            .and_then(|definitions| find_definition(definitions, packet));
+        match defined {
+            Some(defined) => filter.matches_as(packet, defined.name, defined.schema.as_deref()),
+            None => filter.matches(packet),
+        }
+    }
+
+    pub fn display_filter(&self) -> Option<&DisplayFilter> {
+        self.display_filter.as_ref()
+    }
+
+    /// Only shows the packets passing `filter` on top of the other filters, or stops filtering
+    /// by expression if it is `None`.
+    pub fn set_display_filter(&mut self, filter: Option<DisplayFilter>) {
+        self.display_filter_source = filter
+            .as_ref()
+            .map_or_else(String::new, |filter| filter.as_str().to_string());
+        self.display_filter = filter;
+        self.refilter();
+    }
+
+    /// Works out which packets are visible from scratch, needed whenever a filter changed.
+    pub fn refilter(&self) {
+        let mut indices = VecDeque::new();
+        let res = self.packets.read().unwrap().try_for_each(|i, p| {
+            if self.is_packet_visible(i, p) {
+                indices.push_back(i);
+            }
+            Ok(())
+        });
+
+        if let Err(e) = res {
+            tracing::error!("Failed to filter packets: {:?}", e);
+        }
+
+        *self.visible.write().unwrap() = VisiblePackets { indices };
+
+        let pattern = self.search.read().unwrap().pattern().cloned();
+        self.search(pattern);
+    }
+
+    /// Looks for `pattern` among the visible packets, decoding them if the pattern is text.
+    /// Packets that arrive later are checked as they come in.
+    pub fn search(&self, pattern: Option<Pattern>) {
+        let mut matches = VecDeque::new();
+
+        if let Some(pattern) = &pattern {
+            let visible = self.visible.read().unwrap().iter().collect::<Vec<_>>();
+            for index in visible {
+                let Some(packet) = self.packet(index) else {
+                    continue;
+                };
+
+                let decode = || match decode_packet(&packet) {
+                    Some(Ok(decoded)) => Some(decoded.text),
+                    _ => None,
+                };
+
+                if pattern.matches(&packet, decode) {
+                    matches.push_back(index);
+                }
+            }
+        }
+
+        *self.search.write().unwrap() = Search::new(pattern, matches);
+    }
+
+    /// The packet at `index`, read back from disk if it was spilled. `None` once it was dropped.
+    pub fn packet(&self, index: usize) -> Option<Packet> {
+        let mut packet = self.packets.read().unwrap().get(index)?;
+
+        // spilled packets come back with the names of the bundled tables
+        if let Some(definitions) = &self.definitions {
+            apply_definitions(definitions, &mut packet);
+        }
+
+        Some(packet)
+    }
+
+    /// Changes how many packets are kept, dropping or spilling the oldest ones right away if
+    /// there are too many.
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention;
+
+        let mut packets = self.packets.write().unwrap();
+        packets.set_retention(retention);
+        self.forget_evicted(packets.first_index());
+    }
+
+    /// Forgets what is known about the packets before `first`, which are gone.
+    fn forget_evicted(&self, first: usize) {
+        let mut decode_errors = self.decode_errors.write().unwrap();
+        while decode_errors.first().is_some_and(|&i| i < first) {
+            decode_errors.pop_first();
+        }
+
+        let mut trailing_bytes = self.trailing_bytes.write().unwrap();
+        while let Some(entry) = trailing_bytes.first_entry() {
+            if *entry.key() >= first {
+                break;
+            }
+            entry.remove();
+        }
+
+        self.visible.write().unwrap().forget_before(first);
+        self.search.write().unwrap().forget_before(first);
+    }
+
+    pub fn apply_proxy_event(&self, event: ProxyEvent) {
+        match event {
+            ProxyEvent::SessionOpened(session) => {
+                self.sessions.write().unwrap().push(SessionEntry {
+                    session,
+                    closed: None,
+                    trailing: HashMap::new(),
+                });
+            }
+            ProxyEvent::Packet(mut packet) => {
+                if let Some(definitions) = &self.definitions {
+                    apply_definitions(definitions, &mut packet);
+                }
+
+                // only what the flags need, the text is left to searches that look at it
+                let decoded = decode_fields(&packet);
+                let body_len = packet.data.as_ref().map_or(0, |data| data.len());
+                let trailing = match &decoded {
+                    Some(Ok(root)) => Some(body_len - root.len.unwrap_or(body_len)),
+                    _ => None,
+                };
+                if let (Some(trailing), Some(session)) = (trailing, &packet.session) {
+                    self.record_trailing(session.id, &packet, trailing);
+                }
+
+                // before taking the packet buffer lock, text patterns may decode the packet
+                let pattern = self.search.read().unwrap().pattern().cloned();
+                let found = pattern.is_some_and(|pattern| {
+                    pattern.matches(&packet, || Some(decode_packet(&packet)?.ok()?.text))
+                });
+
+                let mut packets = self.packets.write().unwrap();
+                let index = packets.len();
+                match (&decoded, trailing) {
+                    (_, Some(trailing)) if trailing > 0 => {
+                        self.trailing_bytes.write().unwrap().insert(index, trailing);
+                    }
+                    (Some(Err(_)), _) => {
+                        self.decode_errors.write().unwrap().insert(index);
+                    }
+                    _ => {}
+                }
+
+                if self.is_packet_visible(index, &packet) {
+                    self.visible.write().unwrap().indices.push_back(index);
+                    if found {
+                        self.search.write().unwrap().push(index);
+                    }
+                }
+                packets.push(packet);
+                self.forget_evicted(packets.first_index());
+            }
+            ProxyEvent::SessionClosed(session, reason) => {
+                if let Some(entry) = self
+                    .sessions
+                    .write()
+                    .unwrap()
+                    .iter_mut()
+                    .find(|e| e.session.id == session.id)
+                {
+                    entry.closed = Some(reason);
+                }
+            }
+        }
+    }
+
+    /// Loads a packet definitions file for `definitions_protocol`, renaming the packets already
+    /// captured. The proxy picks them up the next time it is started.
+    pub fn load_definitions(&mut self, path: &Path) -> anyhow::Result<()> {
+        let definitions = PacketDefinitions::load(path, self.definitions_protocol)?;
+
+        for packet in self.packets.write().unwrap().iter_mut() {
+            apply_definitions(&definitions, packet);
+        }
+
+        self.definitions_path = Some(path.to_owned());
+        self.definitions = Some(Arc::new(definitions));
+
+        Ok(())
+    }
+
+    /// Stops using the loaded definitions, packets already named by them keep their names.
+    pub fn forget_definitions(&mut self) {
+        self.definitions_path = None;
+        self.definitions = None;
+    }
+
+    fn record_trailing(&self, session: SessionId, packet: &Packet, trailing: usize) {
+        let mut sessions = self.sessions.write().unwrap();
+        let Some(entry) = sessions.iter_mut().find(|e| e.session.id == session) else {
+            return;
+        };
+
+        let stats = entry
+            .trailing
+            .entry((packet.side, packet.state, packet.id))
+            .or_insert(TrailingStats {
+                name: packet.name,
+                decoded: 0,
+                with_trailing: 0,
+                max_trailing: 0,
+            });
+
+        stats.decoded += 1;
+        if trailing > 0 {
+            stats.with_trailing += 1;
+            stats.max_trailing = stats.max_trailing.max(trailing);
+        }
+    }
+
+    pub fn save_capture(&self, path: &Path) -> anyhow::Result<()> {
+        let mut writer = CaptureWriter::new(BufWriter::new(File::create(path)?))?;
+
+        self.packets
+            .read()
+            .unwrap()
+            .try_for_each(|_, packet| writer.write_packet(packet))?;
+
+        for entry in self.sessions.read().unwrap().iter() {
+            if let Some(reason) = &entry.closed {
+                writer.write_event(&ProxyEvent::SessionClosed(
+                    entry.session.clone(),
+                    reason.clone(),
+                ))?;
+            }
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Decodes the selected packet, once per selection.
+    pub fn selected_fields(&mut self) -> Option<Arc<Field>> {
+        let index = self.selected_packet?;
+
+        if self.decoded.as_ref().map(|(i, _)| *i) != Some(index) {
+            let fields = self
+                .packet(index)
+                .as_ref()
+                .and_then(decode_fields)
+                .and_then(Result::ok)
+                .map(Arc::new);
+
+            self.decoded = Some((index, fields));
+            self.selected_field = None;
+        }
+
+        self.decoded.as_ref()?.1.clone()
+    }
+
+    /// Forgets every packet, keeping the sessions that are still alive. Indices start over at 0,
+    /// so nothing cached by index may outlive this.
+    pub fn clear_packets(&mut self) {
+        self.selected_packet = None;
+        self.decoded = None;
+        self.selected_field = None;
+        self.packets.write().unwrap().clear();
+        self.visible.write().unwrap().indices.clear();
+        self.search.write().unwrap().clear();
+        self.decode_errors.write().unwrap().clear();
+        self.trailing_bytes.write().unwrap().clear();
+
+        let mut sessions = self.sessions.write().unwrap();
+        sessions.retain(|e| e.closed.is_none());
+        if let Some(id) = self.session_filter {
+            if !sessions.iter().any(|e| e.session.id == id) {
+                self.session_filter = None;
+            }
+        }
+    }
+
+    /// Forgets every packet and session.
+    pub fn clear_capture(&mut self) {
+        self.clear_packets();
+        self.session_filter = None;
+        self.sessions.write().unwrap().clear();
+    }
+
+    /// Replaces the current packets and sessions with the ones in the capture.
+    pub fn load_capture(&mut self, path: &Path) -> anyhow::Result<()> {
+        let reader = CaptureReader::new(BufReader::new(File::open(path)?))?;
+
+        self.clear_capture();
+
+        for event in reader {
+            self.apply_proxy_event(event?);
+        }
+
+        Ok(())
+    }
+
     pub fn send_event(&self, event: Event) {
         if let Some(sender) = &self.sender {
             sender.send(event);
         }
     }
 }
+
+/// Names and lays out `packet` as `definitions` do, if they are for its protocol version.
+pub(crate) fn apply_definitions(definitions: &PacketDefinitions, packet: &mut Packet) {
+    if let Some(defined) = find_definition(definitions, packet) {
+        packet.name = defined.name;
+        packet.schema = defined.schema.clone();
+    }
+}
+
+/// The packet of `definitions` that `packet` is, if they are for its protocol version.
+fn find_definition<'a>(definitions: &'a PacketDefinitions, packet: &Packet) -> Option<&'a Packet> {
+    let version = packet
+        .session
+        .as_ref()
+        .and_then(|s| s.protocol_version())
+        .unwrap_or(STD_PROTOCOL_VERSION);
+
+    if version != definitions.protocol_version {
+        return None;
+    }
+
+    definitions.find(packet.side, packet.state, packet.id)
+}
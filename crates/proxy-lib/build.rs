@@ -1,9 +1,27 @@
-use std::{env, fs, path::Path, process::Command};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
 
 use anyhow::Context;
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{format_ident, quote};
 use serde::Deserialize;
+use syn::ext::IdentExt;
+
+/// Protocol version of the valence packets the decoders are generated from, its table becomes
+/// `STD_PACKETS`.
+const STD_PROTOCOL_VERSION: i32 = 763;
+
+#[derive(Deserialize)]
+struct Version {
+    version: String,
+    protocol: i32,
+    /// Path of the packet table, relative to `assets/`
+    packets: String,
+}
 
 #[derive(Deserialize)]
 struct Packet {
@@ -13,80 +31,353 @@ struct Packet {
     id: i32,
 }
 
+#[derive(Deserialize)]
+struct Metadata {
+    packages: Vec<MetadataPackage>,
+}
+
+#[derive(Deserialize)]
+struct MetadataPackage {
+    name: String,
+    manifest_path: PathBuf,
+}
+
 pub fn main() -> anyhow::Result<()> {
-    let packets: Vec<Packet> = serde_json::from_str(include_str!("./assets/packets.json"))?;
+    let versions: Vec<Version> = serde_json::from_str(include_str!("./assets/versions.json"))?;
+
+    // only needed for the decoders, which are only compiled with the `decode` feature
+    let packet_fields = if env::var_os("CARGO_FEATURE_DECODE").is_some() {
+        valence_packet_fields()
+            .context("failed to read the packet structs the decoders are generated from")?
+    } else {
+        HashMap::new()
+    };
 
     let mut consts = TokenStream::new();
+    let mut tables: Vec<TokenStream> = Vec::new();
+    let mut decode_arms = TokenStream::new();
+    let mut layout_arms = TokenStream::new();
+
+    for version in &versions {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join(&version.packets);
+        let packets: Vec<Packet> = serde_json::from_str(
+            &fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+        )?;
+
+        let len = packets.len();
+        let protocol = version.protocol;
+        let version_name = &version.version;
+        let table_ident = format_ident!("PACKETS_{}", protocol as u32);
+
+        let mut p: Vec<TokenStream> = Vec::new();
+
+        for packet in packets {
+            let name = valence_name(&packet);
+
+            let id = packet.id;
+            let side = match &packet.side {
+                s if s == "clientbound" => {
+                    quote! { crate::packet_registry::PacketSide::Clientbound }
+                }
+                s if s == "serverbound" => {
+                    quote! { crate::packet_registry::PacketSide::Serverbound }
+                }
+                _ => unreachable!(),
+            };
+
+            let state = match &packet.state {
+                s if s == "handshaking" => {
+                    quote! { crate::packet_registry::PacketState::Handshaking }
+                }
+                s if s == "status" => quote! { crate::packet_registry::PacketState::Status },
+                s if s == "login" => quote! { crate::packet_registry::PacketState::Login },
+                s if s == "play" => quote! { crate::packet_registry::PacketState::Play },
+                _ => unreachable!(),
+            };
 
-    let len = packets.len();
-
-    let mut p: Vec<TokenStream> = Vec::new();
-
-    for packet in packets {
-        let name = packet.name.strip_suffix("Packet").unwrap_or(&packet.name);
-        // lowercase the last character of name
-        let name = {
-            let mut chars = name.chars();
-            let last_char = chars.next_back().unwrap();
-            let last_char = last_char.to_lowercase().to_string();
-            let mut name = chars.collect::<String>();
-            name.push_str(&last_char);
-            name
-        };
-
-        // if the packet is clientbound, but the name does not ends with S2c, add it
-        let name = if packet.side == "clientbound" && !name.ends_with("S2c") {
-            format!("{}S2c", name)
-        } else {
-            name
-        };
-
-        // same for serverbound
-        let name = if packet.side == "serverbound" && !name.ends_with("C2s") {
-            format!("{}C2s", name)
-        } else {
-            name
-        };
-
-        let id = packet.id;
-        let side = match &packet.side {
-            s if s == "clientbound" => quote! { crate::packet_registry::PacketSide::Clientbound },
-            s if s == "serverbound" => quote! { crate::packet_registry::PacketSide::Serverbound },
-            _ => unreachable!(),
-        };
-
-        let state = match &packet.state {
-            s if s == "handshaking" => quote! { crate::packet_registry::PacketState::Handshaking },
-            s if s == "status" => quote! { crate::packet_registry::PacketState::Status },
-            s if s == "login" => quote! { crate::packet_registry::PacketState::Login },
-            s if s == "play" => quote! { crate::packet_registry::PacketState::Play },
-            _ => unreachable!(),
-        };
-
-        // const STD_PACKETS = [PacketSide::Client(PacketState::Handshaking(Packet{..})), ..];
-        p.push(quote! {
-            crate::packet_registry::Packet {
-                id: #id,
-                side: #side,
-                state: #state,
-                timestamp: None,
-                name: #name,
-                data: None,
+            // only valence's own version has types to decode with
+            if protocol == STD_PROTOCOL_VERSION {
+                let ident = syn::parse_str::<syn::Ident>(&name)?;
+                let decode = match packet_fields.get(&name) {
+                    Some(fields) => {
+                        let idents = fields.iter().map(|(ident, _)| ident);
+                        let names = fields.iter().map(|(ident, _)| ident.unraw().to_string());
+                        let types = fields.iter().map(|(_, ty)| ty);
+                        layout_arms.extend(quote! {
+                            (#side, #state, #ident::ID) => Some(&[#((#names, #types)),*]),
+                        });
+
+                        let names = fields.iter().map(|(ident, _)| ident.unraw().to_string());
+                        quote! {
+                            read_fields(data, #name, text, |r| Ok(#ident {
+                                #(#idents: r.read(#names)?,)*
+                            }))
+                        }
+                    }
+                    None => quote! { read_whole::<#ident>(data, #name, text) },
+                };
+                decode_arms.extend(quote! {
+                    (#side, #state, #ident::ID) => Some(#decode),
+                });
+            }
+
+            // const STD_PACKETS = [PacketSide::Client(PacketState::Handshaking(Packet{..})), ..];
+            p.push(quote! {
+                crate::packet_registry::Packet {
+                    id: #id,
+                    side: #side,
+                    state: #state,
+                    timestamp: None,
+                    name: #name,
+                    data: None,
+                    session: None,
+                    schema: None,
+                }
+            });
+        }
+
+        consts.extend(quote! {
+            const #table_ident: [crate::packet_registry::Packet; #len] = [
+                #(#p),*
+            ];
+        });
+
+        if protocol == STD_PROTOCOL_VERSION {
+            consts.extend(quote! {
+                pub const STD_PACKETS: [crate::packet_registry::Packet; #len] = #table_ident;
+            });
+        }
+
+        tables.push(quote! {
+            crate::packet_registry::PacketTable {
+                protocol_version: #protocol,
+                version: #version_name,
+                packets: &#table_ident,
             }
         });
     }
 
-    consts.extend([quote! {
-        pub const STD_PACKETS: [crate::packet_registry::Packet; #len] = [
-            #(#p),*
+    anyhow::ensure!(
+        versions.iter().any(|v| v.protocol == STD_PROTOCOL_VERSION),
+        "assets/versions.json has no packet table for protocol {}",
+        STD_PROTOCOL_VERSION
+    );
+
+    consts.extend(quote! {
+        /// Protocol version of [`STD_PACKETS`], the only one packets can be decoded for.
+        pub const STD_PROTOCOL_VERSION: i32 = #STD_PROTOCOL_VERSION;
+
+        /// Every bundled packet table, one per supported protocol version.
+        pub const PACKET_TABLES: &[crate::packet_registry::PacketTable] = &[
+            #(#tables),*
         ];
-    }]);
+    });
 
     write_generated_file(consts, "packets.rs")?;
 
+    // only compiled with the `decode` feature, see src/decode.rs
+    let decode = quote! {
+        fn decode_std_fields(
+            side: crate::packet_registry::PacketSide,
+            state: crate::packet_registry::PacketState,
+            id: i32,
+            data: &[u8],
+            text: bool,
+        ) -> Option<Result<StdDecoded, DecodeError>> {
+            match (side, state, id) {
+                #decode_arms
+                _ => None,
+            }
+        }
+
+        fn std_layout(
+            side: crate::packet_registry::PacketSide,
+            state: crate::packet_registry::PacketState,
+            id: i32,
+        ) -> Option<&'static [(&'static str, &'static str)]> {
+            match (side, state, id) {
+                #layout_arms
+                _ => None,
+            }
+        }
+    };
+
+    write_generated_file(decode, "decode.rs")?;
+
+    Ok(())
+}
+
+/// Named fields of valence's packet structs and their types, in declaration order, keyed by
+/// type name. Only structs that derive both `Packet` and `Decode` are included, the derived
+/// `Decode` reads their fields in this order and nothing else, so the generated decoders can do
+/// the same one field at a time.
+fn valence_packet_fields() -> anyhow::Result<HashMap<String, Vec<(syn::Ident, String)>>> {
+    let cargo = env::var_os("CARGO").context("failed to get CARGO env var")?;
+    let output = Command::new(cargo)
+        .args([
+            "metadata",
+            "--format-version",
+            "1",
+            "--offline",
+            // packages of other platforms may not have been downloaded
+            "--filter-platform",
+        ])
+        .arg(env::var_os("TARGET").context("failed to get TARGET env var")?)
+        .arg("--manifest-path")
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"))
+        .output()
+        .context("failed to run cargo metadata")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "cargo metadata failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let metadata: Metadata = serde_json::from_slice(&output.stdout)?;
+    let mut fields = HashMap::new();
+
+    for package in &metadata.packages {
+        if !package.name.starts_with("valence") {
+            continue;
+        }
+
+        if let Some(dir) = package.manifest_path.parent() {
+            collect_packet_fields(&dir.join("src"), &mut fields)?;
+        }
+    }
+
+    anyhow::ensure!(
+        !fields.is_empty(),
+        "no packet structs found in the valence sources"
+    );
+    Ok(fields)
+}
+
+fn collect_packet_fields(
+    dir: &Path,
+    fields: &mut HashMap<String, Vec<(syn::Ident, String)>>,
+) -> anyhow::Result<()> {
+    let entries = fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            collect_packet_fields(&path, fields)?;
+        } else if path.extension().map_or(false, |e| e == "rs") {
+            // a skipped file would silently lose the field offsets of its packets
+            let source = fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            let file = syn::parse_file(&source)
+                .with_context(|| format!("failed to parse {}", path.display()))?;
+            collect_items(&file.items, fields);
+        }
+    }
+
     Ok(())
 }
 
+fn collect_items(items: &[syn::Item], fields: &mut HashMap<String, Vec<(syn::Ident, String)>>) {
+    for item in items {
+        match item {
+            syn::Item::Struct(item) if derives(&item.attrs, &["Packet", "Decode"]) => {
+                if let syn::Fields::Named(named) = &item.fields {
+                    let named = named
+                        .named
+                        .iter()
+                        .filter_map(|f| Some((f.ident.clone()?, type_name(&f.ty))));
+                    fields.insert(item.ident.to_string(), named.collect());
+                }
+            }
+            syn::Item::Mod(item) => {
+                if let Some((_, items)) = &item.content {
+                    collect_items(items, fields);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A field's type without lifetimes and module paths, the way `FieldReader` names it, e.g.
+/// `Cow<'a, str>` becomes `Cow<str>`.
+fn type_name(ty: &syn::Type) -> String {
+    let tokens = quote!(#ty).to_string();
+    let mut parts = tokens.split_whitespace().peekable();
+    let mut name = String::new();
+
+    while let Some(part) = parts.next() {
+        if part.starts_with('\'') {
+            // and the comma separating it from the next generic argument
+            parts.next_if_eq(&",");
+            continue;
+        }
+
+        if part == "::" {
+            let ident_start = name
+                .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map_or(0, |i| i + 1);
+            name.truncate(ident_start);
+            continue;
+        }
+
+        name.push_str(part);
+        // spaced like `std::any::type_name` spaces them, e.g. `[u8; 16]`
+        if part == ";" || part == "," {
+            name.push(' ');
+        }
+    }
+
+    name
+}
+
+/// Whether `attrs` derive every one of `traits`.
+fn derives(attrs: &[syn::Attribute], traits: &[&str]) -> bool {
+    let mut derived = Vec::new();
+
+    for attr in attrs.iter().filter(|a| a.path().is_ident("derive")) {
+        let _ = attr.parse_nested_meta(|meta| {
+            if let Some(ident) = meta.path.segments.last() {
+                derived.push(ident.ident.to_string());
+            }
+            Ok(())
+        });
+    }
+
+    traits.iter().all(|t| derived.iter().any(|d| d == t))
+}
+
+/// Name of the valence type for a packet, e.g. `HandshakeC2SPacket` becomes `HandshakeC2s`.
+fn valence_name(packet: &Packet) -> String {
+    let name = packet.name.strip_suffix("Packet").unwrap_or(&packet.name);
+    // lowercase the last character of name
+    let name = {
+        let mut chars = name.chars();
+        let last_char = chars.next_back().unwrap();
+        let last_char = last_char.to_lowercase().to_string();
+        let mut name = chars.collect::<String>();
+        name.push_str(&last_char);
+        name
+    };
+
+    // if the packet is clientbound, but the name does not ends with S2c, add it
+    let name = if packet.side == "clientbound" && !name.ends_with("S2c") {
+        format!("{}S2c", name)
+    } else {
+        name
+    };
+
+    // same for serverbound
+    if packet.side == "serverbound" && !name.ends_with("C2s") {
+        format!("{}C2s", name)
+    } else {
+        name
+    }
+}
+
 pub fn write_generated_file(content: TokenStream, out_file: &str) -> anyhow::Result<()> {
     let out_dir = env::var_os("OUT_DIR").context("failed to get OUT_DIR env var")?;
     let path = Path::new(&out_dir).join(out_file);
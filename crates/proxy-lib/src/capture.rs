@@ -0,0 +1,543 @@
+//! Versioned on-disk format for captured sessions.
+//!
+//! A capture starts with a header followed by length-prefixed records, all integers are big
+//! endian:
+//!
+//! ```text
+//! header:  magic "PICF" | format version u16 | decoder protocol version i32 | created at (time)
+//! record:  kind u8 | payload length u32 | payload
+//!
+//! session opened (0): session id u64 | client addr (str) | server addr (str) | accepted at (time)
+//! packet         (1): session id u64 | side u8 | state u8 | packet id i32 | timestamp (time?)
+//!                     | body (rest of the payload)
+//! session closed (2): session id u64 | reason u8 | message (str)
+//!
+//! str:   length u16 | utf-8 bytes
+//! time:  unix timestamp in nanoseconds i128 | utc offset in seconds i32
+//! time?: present u8 | time
+//! ```
+//!
+//! Readers skip record kinds they don't know about, so new kinds can be added without bumping
+//! the format version.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io::{self, Read, Seek, SeekFrom, Write},
+    sync::{Arc, OnceLock},
+};
+
+use anyhow::{bail, ensure, Context};
+use bytes::Bytes;
+use time::{OffsetDateTime, UtcOffset};
+use valence_core::protocol::Decode;
+use valence_network::packet::HandshakeC2s;
+
+use crate::{
+    connection_state::ConnectionState,
+    packet_registry::{
+        bundled_packets, find_packet_name, now, Packet, PacketSide, PacketState, ProxyEvent,
+    },
+    session::{CloseReason, Session, SessionId},
+    STD_PROTOCOL_VERSION,
+};
+
+const MAGIC: [u8; 4] = *b"PICF";
+pub const CAPTURE_FORMAT_VERSION: u16 = 1;
+
+/// Larger than any valid packet, anything bigger means the file is corrupt.
+const MAX_RECORD_LEN: u32 = 16 * 1024 * 1024;
+
+const RECORD_SESSION_OPENED: u8 = 0;
+const RECORD_PACKET: u8 = 1;
+const RECORD_SESSION_CLOSED: u8 = 2;
+
+#[derive(Clone, Debug)]
+pub struct CaptureHeader {
+    pub format_version: u16,
+    /// [`STD_PROTOCOL_VERSION`](crate::STD_PROTOCOL_VERSION) of the proxy that wrote the
+    /// capture. Says nothing about the sessions, which get their version from their handshake.
+    pub decoder_protocol_version: i32,
+    pub created_at: OffsetDateTime,
+}
+
+pub struct CaptureWriter<W: Write> {
+    writer: W,
+    /// Sessions that already have an opened record in the file
+    written_sessions: HashSet<SessionId>,
+    /// Bytes written so far, header included
+    position: u64,
+}
+
+impl<W: Write> CaptureWriter<W> {
+    pub fn new(mut writer: W) -> anyhow::Result<Self> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&CAPTURE_FORMAT_VERSION.to_be_bytes());
+        header.extend_from_slice(&STD_PROTOCOL_VERSION.to_be_bytes());
+        write_time(&mut header, now());
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            writer,
+            written_sessions: HashSet::new(),
+            position: header.len() as u64,
+        })
+    }
+
+    pub fn write_event(&mut self, event: &ProxyEvent) -> anyhow::Result<()> {
+        match event {
+            ProxyEvent::SessionOpened(session) => self.write_session(session),
+            ProxyEvent::Packet(packet) => self.write_packet(packet),
+            ProxyEvent::SessionClosed(session, reason) => {
+                self.write_session(session)?;
+
+                let mut payload = Vec::new();
+                payload.extend_from_slice(&session.id.0.to_be_bytes());
+                let (tag, message) = match reason {
+                    CloseReason::ClientDisconnected => (0, ""),
+                    CloseReason::ServerDisconnected => (1, ""),
+                    CloseReason::ServerUnreachable(e) => (2, e.as_str()),
+                    CloseReason::Shutdown => (3, ""),
+                    CloseReason::Error(e) => (4, e.as_str()),
+                };
+                payload.push(tag);
+                write_str(&mut payload, message)?;
+
+                self.write_record(RECORD_SESSION_CLOSED, &payload)
+            }
+        }
+    }
+
+    /// Writes a packet, preceded by the opened record of its session if that wasn't written yet.
+    pub fn write_packet(&mut self, packet: &Packet) -> anyhow::Result<()> {
+        let session_id = match &packet.session {
+            Some(session) => {
+                self.write_session(session)?;
+                session.id.0
+            }
+            None => 0,
+        };
+
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&session_id.to_be_bytes());
+        payload.push(match packet.side {
+            PacketSide::Clientbound => 0,
+            PacketSide::Serverbound => 1,
+        });
+        payload.push(match packet.state {
+            PacketState::Handshaking => 0,
+            PacketState::Status => 1,
+            PacketState::Login => 2,
+            PacketState::Play => 3,
+        });
+        payload.extend_from_slice(&packet.id.to_be_bytes());
+        match packet.timestamp {
+            Some(timestamp) => {
+                payload.push(1);
+                write_time(&mut payload, timestamp);
+            }
+            None => payload.push(0),
+        }
+        if let Some(data) = &packet.data {
+            payload.extend_from_slice(data);
+        }
+
+        self.write_record(RECORD_PACKET, &payload)
+    }
+
+    fn write_session(&mut self, session: &Session) -> anyhow::Result<()> {
+        if !self.written_sessions.insert(session.id) {
+            return Ok(());
+        }
+
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&session.id.0.to_be_bytes());
+        write_str(&mut payload, &session.client_addr.to_string())?;
+        write_str(&mut payload, &session.server_addr.to_string())?;
+        write_time(&mut payload, session.accepted_at);
+
+        self.write_record(RECORD_SESSION_OPENED, &payload)
+    }
+
+    fn write_record(&mut self, kind: u8, payload: &[u8]) -> anyhow::Result<()> {
+        ensure!(
+            payload.len() <= MAX_RECORD_LEN as usize,
+            "capture record exceeds maximum length"
+        );
+
+        self.writer.write_all(&[kind])?;
+        self.writer
+            .write_all(&(payload.len() as u32).to_be_bytes())?;
+        self.writer.write_all(payload)?;
+        self.position += 1 + 4 + payload.len() as u64;
+
+        Ok(())
+    }
+
+    /// Offset in the file the next record starts at, to come back to it with
+    /// [`CaptureReader::seek`].
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+pub struct CaptureReader<R: Read> {
+    reader: R,
+    header: CaptureHeader,
+    sessions: HashMap<u64, Arc<Session>>,
+}
+
+impl<R: Read> CaptureReader<R> {
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let mut magic = [0u8; 4];
+        reader
+            .read_exact(&mut magic)
+            .context("not a capture file")?;
+        ensure!(magic == MAGIC, "not a capture file");
+
+        let mut fixed = [0u8; 2 + 4 + 20];
+        reader.read_exact(&mut fixed)?;
+        let mut r = &fixed[..];
+
+        let format_version = read_u16(&mut r)?;
+        ensure!(
+            format_version <= CAPTURE_FORMAT_VERSION,
+            "capture format version {format_version} is newer than supported \
+             ({CAPTURE_FORMAT_VERSION})"
+        );
+
+        let header = CaptureHeader {
+            format_version,
+            decoder_protocol_version: read_i32(&mut r)?,
+            created_at: read_time(&mut r)?,
+        };
+
+        Ok(Self {
+            reader,
+            header,
+            sessions: HashMap::new(),
+        })
+    }
+
+    pub fn header(&self) -> &CaptureHeader {
+        &self.header
+    }
+
+    /// Returns `None` once the end of the capture is reached.
+    pub fn read_event(&mut self) -> anyhow::Result<Option<ProxyEvent>> {
+        loop {
+            let mut kind = [0u8; 1];
+            match self.reader.read_exact(&mut kind) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+
+            let mut len = [0u8; 4];
+            self.reader.read_exact(&mut len)?;
+            let len = u32::from_be_bytes(len);
+            ensure!(
+                len <= MAX_RECORD_LEN,
+                "capture record exceeds maximum length"
+            );
+
+            let mut payload = vec![0u8; len as usize];
+            self.reader.read_exact(&mut payload)?;
+            let mut r = &payload[..];
+
+            match kind[0] {
+                RECORD_SESSION_OPENED => {
+                    let id = read_u64(&mut r)?;
+                    let session = Arc::new(Session {
+                        id: SessionId(id),
+                        client_addr: read_str(&mut r)?.parse()?,
+                        server_addr: read_str(&mut r)?.parse()?,
+                        accepted_at: read_time(&mut r)?,
+                        protocol_version: OnceLock::new(),
+                        connection: Arc::new(ConnectionState::new()),
+                    });
+                    // sessions given to `add_session` stay the ones packets refer to
+                    let session = self.sessions.entry(id).or_insert(session).clone();
+
+                    return Ok(Some(ProxyEvent::SessionOpened(session)));
+                }
+                RECORD_PACKET => {
+                    let session = match read_u64(&mut r)? {
+                        0 => None,
+                        id => Some(self.session(id)?),
+                    };
+                    let side = match read_u8(&mut r)? {
+                        0 => PacketSide::Clientbound,
+                        1 => PacketSide::Serverbound,
+                        side => bail!("invalid packet side {side}"),
+                    };
+                    let state = match read_u8(&mut r)? {
+                        0 => PacketState::Handshaking,
+                        1 => PacketState::Status,
+                        2 => PacketState::Login,
+                        3 => PacketState::Play,
+                        state => bail!("invalid packet state {state}"),
+                    };
+                    let id = read_i32(&mut r)?;
+                    let timestamp = match read_u8(&mut r)? {
+                        0 => None,
+                        _ => Some(read_time(&mut r)?),
+                    };
+
+                    // the protocol version isn't stored, the recorded handshake has it
+                    if let Some(session) = &session {
+                        if (side, state, id)
+                            == (PacketSide::Serverbound, PacketState::Handshaking, 0)
+                        {
+                            if let Ok(handshake) = HandshakeC2s::decode(&mut &r[..]) {
+                                let _ = session.protocol_version.set(handshake.protocol_version.0);
+                            }
+                        }
+                    }
+                    let protocol_version = session.as_ref().and_then(|s| s.protocol_version());
+
+                    return Ok(Some(ProxyEvent::Packet(Packet {
+                        side,
+                        state,
+                        id,
+                        timestamp,
+                        name: find_packet_name(bundled_packets(protocol_version), side, state, id),
+                        data: Some(Bytes::copy_from_slice(r)),
+                        session,
+                        schema: None,
+                    })));
+                }
+                RECORD_SESSION_CLOSED => {
+                    let session = self.session(read_u64(&mut r)?)?;
+                    let tag = read_u8(&mut r)?;
+                    let message = read_str(&mut r)?;
+                    let reason = match tag {
+                        0 => CloseReason::ClientDisconnected,
+                        1 => CloseReason::ServerDisconnected,
+                        2 => CloseReason::ServerUnreachable(message),
+                        3 => CloseReason::Shutdown,
+                        4 => CloseReason::Error(message),
+                        tag => bail!("invalid close reason {tag}"),
+                    };
+
+                    return Ok(Some(ProxyEvent::SessionClosed(session, reason)));
+                }
+                // written by a newer version, not something we can show
+                _ => continue,
+            }
+        }
+    }
+
+    /// Makes packets of `session` readable without reading its opened record first, for readers
+    /// that [`seek`](Self::seek) past it.
+    pub fn add_session(&mut self, session: Arc<Session>) {
+        self.sessions.insert(session.id.0, session);
+    }
+
+    fn session(&self, id: u64) -> anyhow::Result<Arc<Session>> {
+        self.sessions
+            .get(&id)
+            .cloned()
+            .with_context(|| format!("record refers to unknown session #{id}"))
+    }
+}
+
+/// Sends `events` to `sender` spaced out the way they were captured, `speed` times as fast.
+///
+/// Returns once every event is sent or the receiving side is gone.
+pub async fn replay_timed(
+    events: impl IntoIterator<Item = ProxyEvent>,
+    sender: flume::Sender<ProxyEvent>,
+    speed: f64,
+) {
+    let speed = speed.max(0.01);
+    let start = tokio::time::Instant::now();
+    let mut first: Option<OffsetDateTime> = None;
+
+    for event in events {
+        let timestamp = match &event {
+            ProxyEvent::SessionOpened(session) => Some(session.accepted_at),
+            ProxyEvent::Packet(packet) => packet.timestamp,
+            ProxyEvent::SessionClosed(..) => None,
+        };
+
+        if let Some(timestamp) = timestamp {
+            let first = *first.get_or_insert(timestamp);
+            let offset = (timestamp - first).as_seconds_f64() / speed;
+            if offset > 0.0 {
+                tokio::time::sleep_until(start + std::time::Duration::from_secs_f64(offset)).await;
+            }
+        }
+
+        if sender.send_async(event).await.is_err() {
+            return;
+        }
+    }
+}
+
+impl<R: Read + Seek> CaptureReader<R> {
+    /// Continues reading at `offset`, which has to be the start of a record as returned by
+    /// [`CaptureWriter::position`].
+    pub fn seek(&mut self, offset: u64) -> io::Result<()> {
+        self.reader.seek(SeekFrom::Start(offset))?;
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = anyhow::Result<ProxyEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_event().transpose()
+    }
+}
+
+fn write_str(buf: &mut Vec<u8>, s: &str) -> anyhow::Result<()> {
+    ensure!(s.len() <= u16::MAX as usize, "string too long for capture");
+    buf.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+fn write_time(buf: &mut Vec<u8>, time: OffsetDateTime) {
+    buf.extend_from_slice(&time.unix_timestamp_nanos().to_be_bytes());
+    buf.extend_from_slice(&time.offset().whole_seconds().to_be_bytes());
+}
+
+fn take<'a>(r: &mut &'a [u8], len: usize) -> anyhow::Result<&'a [u8]> {
+    ensure!(r.len() >= len, "capture record is truncated");
+    let (head, tail) = r.split_at(len);
+    *r = tail;
+    Ok(head)
+}
+
+fn read_u8(r: &mut &[u8]) -> anyhow::Result<u8> {
+    Ok(take(r, 1)?[0])
+}
+
+fn read_u16(r: &mut &[u8]) -> anyhow::Result<u16> {
+    Ok(u16::from_be_bytes(take(r, 2)?.try_into()?))
+}
+
+fn read_i32(r: &mut &[u8]) -> anyhow::Result<i32> {
+    Ok(i32::from_be_bytes(take(r, 4)?.try_into()?))
+}
+
+fn read_u64(r: &mut &[u8]) -> anyhow::Result<u64> {
+    Ok(u64::from_be_bytes(take(r, 8)?.try_into()?))
+}
+
+fn read_str(r: &mut &[u8]) -> anyhow::Result<String> {
+    let len = read_u16(r)? as usize;
+    Ok(String::from_utf8(take(r, len)?.to_vec())?)
+}
+
+fn read_time(r: &mut &[u8]) -> anyhow::Result<OffsetDateTime> {
+    let nanos = i128::from_be_bytes(take(r, 16)?.try_into()?);
+    let offset = UtcOffset::from_whole_seconds(read_i32(r)?)?;
+
+    Ok(OffsetDateTime::from_unix_timestamp_nanos(nanos)?.to_offset(offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(nanos: i128) -> OffsetDateTime {
+        OffsetDateTime::from_unix_timestamp_nanos(nanos)
+            .unwrap()
+            .to_offset(UtcOffset::from_whole_seconds(3600).unwrap())
+    }
+
+    fn packet(session: &Arc<Session>, side: PacketSide, id: i32, body: &[u8]) -> Packet {
+        Packet {
+            side,
+            state: PacketState::Play,
+            id,
+            timestamp: Some(at(1_700_000_000_123_456_789)),
+            name: "",
+            data: Some(Bytes::copy_from_slice(body)),
+            session: Some(session.clone()),
+            schema: None,
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let session = Arc::new(Session {
+            id: SessionId(7),
+            client_addr: "127.0.0.1:50000".parse().unwrap(),
+            server_addr: "127.0.0.1:25565".parse().unwrap(),
+            accepted_at: at(1_700_000_000_000_000_000),
+            protocol_version: OnceLock::new(),
+            connection: Arc::new(ConnectionState::new()),
+        });
+
+        let events = [
+            ProxyEvent::SessionOpened(session.clone()),
+            ProxyEvent::Packet(packet(&session, PacketSide::Serverbound, 0x14, &[1, 2, 3])),
+            ProxyEvent::Packet(packet(&session, PacketSide::Clientbound, 0x20, &[])),
+            ProxyEvent::SessionClosed(session.clone(), CloseReason::Error("boom".to_string())),
+        ];
+
+        let mut writer = CaptureWriter::new(Vec::new()).unwrap();
+        let mut positions = Vec::new();
+        for event in &events {
+            positions.push(writer.position());
+            writer.write_event(event).unwrap();
+        }
+        let bytes = writer.into_inner();
+
+        let mut reader = CaptureReader::new(io::Cursor::new(&bytes)).unwrap();
+        assert_eq!(reader.header().format_version, CAPTURE_FORMAT_VERSION);
+        assert_eq!(
+            reader.header().decoder_protocol_version,
+            STD_PROTOCOL_VERSION
+        );
+
+        let read = reader.by_ref().collect::<anyhow::Result<Vec<_>>>().unwrap();
+        assert_eq!(read.len(), events.len());
+
+        let ProxyEvent::SessionOpened(read_session) = &read[0] else {
+            panic!("expected a session opened event, got {:?}", read[0]);
+        };
+        assert_eq!(read_session.id, session.id);
+        assert_eq!(read_session.client_addr, session.client_addr);
+        assert_eq!(read_session.server_addr, session.server_addr);
+        assert_eq!(read_session.accepted_at, session.accepted_at);
+
+        for (read, written) in read[1..3].iter().zip(&events[1..3]) {
+            let (ProxyEvent::Packet(read), ProxyEvent::Packet(written)) = (read, written) else {
+                panic!("expected packets, got {:?}", read);
+            };
+            assert_eq!(read, written);
+            assert_eq!(read.timestamp, written.timestamp);
+            assert_eq!(read.session.as_ref().unwrap().id, session.id);
+        }
+
+        let ProxyEvent::SessionClosed(_, reason) = &read[3] else {
+            panic!("expected a session closed event, got {:?}", read[3]);
+        };
+        assert_eq!(*reason, CloseReason::Error("boom".to_string()));
+
+        // records can be read again from where the writer said they start
+        reader.seek(positions[2]).unwrap();
+        match reader.read_event().unwrap() {
+            Some(ProxyEvent::Packet(packet)) => assert_eq!(packet.id, 0x20),
+            event => panic!("expected the second packet, got {:?}", event),
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(CaptureReader::new(&b"not a capture"[..]).is_err());
+    }
+}
@@ -0,0 +1,352 @@
+//! Decoders for the payloads of plugin channels, the data carried by `CustomPayloadC2s` and
+//! `CustomPayloadS2c` that valence only sees as bytes.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use anyhow::{ensure, Context};
+
+use crate::{
+    fields::{read_var_int, Field},
+    schema::PacketSchema,
+};
+
+/// Decodes the payload of one plugin channel into a tree of fields.
+///
+/// Offsets in the returned tree are relative to the start of the payload.
+pub trait ChannelDecoder: Send + Sync {
+    fn decode(&self, payload: &[u8]) -> anyhow::Result<Field>;
+}
+
+/// Channels can be described in the same way as packets in definitions files.
+impl ChannelDecoder for PacketSchema {
+    fn decode(&self, payload: &[u8]) -> anyhow::Result<Field> {
+        let (field, _) = PacketSchema::decode(self, "payload", payload)?;
+        Ok(field)
+    }
+}
+
+fn registry() -> &'static RwLock<HashMap<String, Arc<dyn ChannelDecoder>>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, Arc<dyn ChannelDecoder>>>> = OnceLock::new();
+
+    REGISTRY.get_or_init(|| {
+        let mut decoders: HashMap<String, Arc<dyn ChannelDecoder>> = HashMap::new();
+        decoders.insert("minecraft:brand".to_string(), Arc::new(Brand));
+        decoders.insert("minecraft:register".to_string(), Arc::new(ChannelList));
+        decoders.insert("minecraft:unregister".to_string(), Arc::new(ChannelList));
+        decoders.insert("bungeecord:main".to_string(), Arc::new(BungeeCord));
+        // the name BungeeCord used before namespaced channels
+        decoders.insert("BungeeCord".to_string(), Arc::new(BungeeCord));
+        RwLock::new(decoders)
+    })
+}
+
+/// Decodes the payload of `channel` with `decoder` from now on, replacing any decoder the
+/// channel had, built-in ones included.
+pub fn register_channel_decoder(channel: &str, decoder: Arc<dyn ChannelDecoder>) {
+    registry()
+        .write()
+        .unwrap()
+        .insert(channel.to_string(), decoder);
+}
+
+/// Adds the decoded payload of a custom payload packet to its field tree, as a `payload` child
+/// of `root`. Does nothing for channels without a decoder.
+pub(crate) fn decode_payload(root: &mut Field, body: &[u8]) {
+    let Some((len, prefix)) = read_var_int(body) else {
+        return;
+    };
+    let Some(channel) = body
+        .get(prefix..prefix.saturating_add(len as usize))
+        .and_then(|b| std::str::from_utf8(b).ok())
+    else {
+        return;
+    };
+
+    let Some(decoder) = registry().read().unwrap().get(channel).cloned() else {
+        return;
+    };
+
+    let start = prefix + channel.len();
+    let payload = &body[start..];
+
+    let mut field = match decoder.decode(payload) {
+        Ok(field) => field,
+        Err(e) => Field::leaf(String::new(), "error", format!("{:#}", e)),
+    };
+
+    field.name = "payload".to_string();
+    field.offset.get_or_insert(0);
+    field.len.get_or_insert(payload.len());
+    shift(&mut field, start);
+
+    root.children.push(field);
+}
+
+fn shift(field: &mut Field, by: usize) {
+    if let Some(offset) = &mut field.offset {
+        *offset += by;
+    }
+
+    for child in &mut field.children {
+        shift(child, by);
+    }
+}
+
+/// The server or client brand, e.g. `vanilla`.
+struct Brand;
+
+impl ChannelDecoder for Brand {
+    fn decode(&self, payload: &[u8]) -> anyhow::Result<Field> {
+        let (len, prefix) = read_var_int(payload).context("missing brand length")?;
+        let brand = payload
+            .get(prefix..prefix.saturating_add(len as usize))
+            .context("brand is longer than the payload")?;
+
+        let mut field = Field::leaf(
+            "brand".to_string(),
+            "str",
+            std::str::from_utf8(brand)?.to_string(),
+        );
+        field.offset = Some(0);
+        field.len = Some(prefix + brand.len());
+
+        Ok(Field::node(String::new(), "Brand", vec![field]))
+    }
+}
+
+/// Channels a side can receive, separated by NUL bytes.
+struct ChannelList;
+
+impl ChannelDecoder for ChannelList {
+    fn decode(&self, payload: &[u8]) -> anyhow::Result<Field> {
+        let mut channels = Vec::new();
+        let mut offset = 0;
+
+        for name in payload.split(|&b| b == 0) {
+            if !name.is_empty() {
+                let mut field = Field::leaf(
+                    format!("[{}]", channels.len()),
+                    "str",
+                    std::str::from_utf8(name)?.to_string(),
+                );
+                field.offset = Some(offset);
+                field.len = Some(name.len());
+                channels.push(field);
+            }
+
+            offset += name.len() + 1;
+        }
+
+        Ok(Field::node(String::new(), "list", channels))
+    }
+}
+
+/// BungeeCord's plugin messaging: a subchannel followed by its arguments, written with Java's
+/// `DataOutput`. Arguments are shown as strings as far as they read as such, the rest as bytes.
+struct BungeeCord;
+
+impl ChannelDecoder for BungeeCord {
+    fn decode(&self, payload: &[u8]) -> anyhow::Result<Field> {
+        let mut fields = Vec::new();
+        let mut offset = 0;
+
+        while let Some((s, len)) = read_java_utf(&payload[offset..]) {
+            let name = match fields.len() {
+                0 => "subchannel".to_string(),
+                i => format!("[{}]", i - 1),
+            };
+
+            let mut field = Field::leaf(name, "str", s.to_string());
+            field.offset = Some(offset);
+            field.len = Some(len);
+            fields.push(field);
+
+            offset += len;
+        }
+
+        ensure!(!fields.is_empty(), "missing subchannel");
+
+        if offset < payload.len() {
+            let rest = &payload[offset..];
+            let mut field = Field::leaf(
+                "data".to_string(),
+                "bytes",
+                rest.iter().map(|b| format!("{:02x}", b)).collect(),
+            );
+            field.offset = Some(offset);
+            field.len = Some(rest.len());
+            fields.push(field);
+        }
+
+        Ok(Field::node(String::new(), "BungeeCord", fields))
+    }
+}
+
+/// Reads a `u16` length prefixed string, returning it with its encoded length.
+fn read_java_utf(bytes: &[u8]) -> Option<(&str, usize)> {
+    let len = u16::from_be_bytes(bytes.get(..2)?.try_into().unwrap()) as usize;
+    let s = std::str::from_utf8(bytes.get(2..2 + len)?).ok()?;
+    Some((s, 2 + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Body of a custom payload packet on `channel`
+    fn body(channel: &str, payload: &[u8]) -> Vec<u8> {
+        let mut body = vec![channel.len() as u8];
+        body.extend_from_slice(channel.as_bytes());
+        body.extend_from_slice(payload);
+        body
+    }
+
+    fn java_utf(s: &str) -> Vec<u8> {
+        let mut bytes = (s.len() as u16).to_be_bytes().to_vec();
+        bytes.extend_from_slice(s.as_bytes());
+        bytes
+    }
+
+    /// Name, value and byte range of every child of `field`
+    fn children(field: &Field) -> Vec<(&str, &str, std::ops::Range<usize>)> {
+        field
+            .children
+            .iter()
+            .map(|f| (f.name.as_str(), f.value.as_str(), f.range().unwrap()))
+            .collect()
+    }
+
+    fn decoded_payload(body: &[u8]) -> Option<Field> {
+        let mut root = Field::node(String::new(), "CustomPayloadS2c", Vec::new());
+        decode_payload(&mut root, body);
+        root.children.pop()
+    }
+
+    /// A payload of a single byte nested two levels deep
+    struct Nested;
+
+    impl ChannelDecoder for Nested {
+        fn decode(&self, _payload: &[u8]) -> anyhow::Result<Field> {
+            let mut leaf = Field::leaf("byte".to_string(), "u8", "1".to_string());
+            leaf.offset = Some(1);
+            leaf.len = Some(1);
+            let mut inner = Field::node("inner".to_string(), "Inner", vec![leaf]);
+            inner.offset = Some(1);
+            inner.len = Some(1);
+            Ok(Field::node(String::new(), "Nested", vec![inner]))
+        }
+    }
+
+    struct Failing;
+
+    impl ChannelDecoder for Failing {
+        fn decode(&self, _payload: &[u8]) -> anyhow::Result<Field> {
+            anyhow::bail!("no good")
+        }
+    }
+
+    #[test]
+    fn payload_offsets_are_shifted_into_the_body() {
+        register_channel_decoder("test:nested", Arc::new(Nested));
+        let body = body("test:nested", &[0, 1]);
+
+        let payload = decoded_payload(&body).unwrap();
+
+        assert_eq!(payload.name, "payload");
+        assert_eq!(payload.range(), Some(12..14));
+        let inner = &payload.children[0];
+        assert_eq!(inner.range(), Some(13..14));
+        assert_eq!(inner.children[0].range(), Some(13..14));
+        assert_eq!(body[13], 1);
+    }
+
+    #[test]
+    fn failing_decoder_leaves_an_error_over_the_payload() {
+        register_channel_decoder("test:failing", Arc::new(Failing));
+        let body = body("test:failing", &[1, 2, 3]);
+
+        let payload = decoded_payload(&body).unwrap();
+
+        assert_eq!(
+            (payload.ty.as_str(), payload.value.as_str()),
+            ("error", "no good")
+        );
+        assert_eq!(payload.range(), Some(13..16));
+    }
+
+    #[test]
+    fn unknown_channels_are_left_alone() {
+        assert_eq!(decoded_payload(&body("test:unknown", &[1, 2])), None);
+        // channel name longer than the body
+        assert_eq!(decoded_payload(&[40, b'a']), None);
+    }
+
+    #[test]
+    fn brand() {
+        let body = body("minecraft:brand", b"\x07vanilla");
+
+        let payload = decoded_payload(&body).unwrap();
+
+        assert_eq!(payload.ty, "Brand");
+        assert_eq!(children(&payload), [("brand", "vanilla", 16..24)]);
+        assert_eq!(&body[17..24], b"vanilla");
+    }
+
+    #[test]
+    fn brand_longer_than_payload() {
+        let e = Brand.decode(b"\x08vanilla").unwrap_err();
+
+        assert_eq!(e.to_string(), "brand is longer than the payload");
+    }
+
+    #[test]
+    fn channel_list() {
+        let body = body("minecraft:register", b"a:b\0\0c:d");
+
+        let payload = decoded_payload(&body).unwrap();
+
+        assert_eq!(
+            children(&payload),
+            [("[0]", "a:b", 19..22), ("[1]", "c:d", 24..27)]
+        );
+        assert_eq!(&body[24..27], b"c:d");
+    }
+
+    #[test]
+    fn bungeecord() {
+        let mut payload = java_utf("Connect");
+        payload.extend(java_utf("lobby"));
+        payload.push(0xff);
+        let body = body("bungeecord:main", &payload);
+
+        let payload = decoded_payload(&body).unwrap();
+
+        assert_eq!(
+            children(&payload),
+            [
+                ("subchannel", "Connect", 16..25),
+                ("[0]", "lobby", 25..32),
+                ("data", "ff", 32..33),
+            ]
+        );
+        assert_eq!(&body[27..32], b"lobby");
+    }
+
+    #[test]
+    fn bungeecord_needs_a_subchannel() {
+        let e = BungeeCord.decode(&[0xff]).unwrap_err();
+
+        assert_eq!(e.to_string(), "missing subchannel");
+    }
+
+    #[test]
+    fn reads_java_utf() {
+        assert_eq!(read_java_utf(b"\0\x02hi!"), Some(("hi", 4)));
+        assert_eq!(read_java_utf(b"\0\x03hi"), None);
+        assert_eq!(read_java_utf(b"\0\x01\xff"), None);
+        assert_eq!(read_java_utf(b"\0"), None);
+    }
+}
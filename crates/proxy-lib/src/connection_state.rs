@@ -0,0 +1,70 @@
+use std::sync::RwLock;
+
+use valence_core::protocol::decode::PacketFrame;
+use valence_network::packet::{
+    HandshakeC2s, HandshakeNextState, LoginCompressionS2c, LoginSuccessS2c,
+};
+
+use crate::{extrapolate_packet, packet_registry::PacketState};
+
+/// Connection state and compression threshold of one connection, advanced by the packets that
+/// change them. Shared by both directions of the relay in `Proxy::process` through the session,
+/// and used by the replay driver, so they can't disagree on when a connection moves on.
+#[derive(Debug)]
+pub(crate) struct ConnectionState {
+    state: RwLock<PacketState>,
+    threshold: RwLock<Option<u32>>,
+}
+
+impl ConnectionState {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: RwLock::new(PacketState::Handshaking),
+            threshold: RwLock::new(None),
+        }
+    }
+
+    pub(crate) fn state(&self) -> PacketState {
+        *self.state.read().unwrap()
+    }
+
+    pub(crate) fn threshold(&self) -> Option<u32> {
+        *self.threshold.read().unwrap()
+    }
+
+    /// Follows a serverbound packet sent in `state`, returning it if it was the handshake.
+    pub(crate) fn track_serverbound(
+        &self,
+        state: PacketState,
+        packet: &PacketFrame,
+    ) -> Option<HandshakeC2s> {
+        if state != PacketState::Handshaking {
+            return None;
+        }
+
+        let handshake = extrapolate_packet::<HandshakeC2s>(packet)?;
+        *self.state.write().unwrap() = match handshake.next_state {
+            HandshakeNextState::Status => PacketState::Status,
+            HandshakeNextState::Login => PacketState::Login,
+        };
+
+        Some(handshake)
+    }
+
+    /// Follows a clientbound packet sent in `state`.
+    pub(crate) fn track_clientbound(&self, state: PacketState, packet: &PacketFrame) {
+        if state != PacketState::Login {
+            return;
+        }
+
+        if let Some(compression) = extrapolate_packet::<LoginCompressionS2c>(packet) {
+            if compression.threshold.0 >= 0 {
+                *self.threshold.write().unwrap() = Some(compression.threshold.0 as u32);
+            }
+        }
+
+        if extrapolate_packet::<LoginSuccessS2c>(packet).is_some() {
+            *self.state.write().unwrap() = PacketState::Play;
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use std::fmt::{self, Debug, Display};
+
+use bytes::Bytes;
+
+use valence::protocol::{Decode, Packet};
+
+use valence::advancement::packet::*;
+use valence::client::action::*;
+use valence::client::command::*;
+use valence::client::custom_payload::*;
+use valence::client::hand_swing::*;
+use valence::client::interact_block::*;
+use valence::client::interact_entity::*;
+use valence::client::interact_item::*;
+use valence::client::keepalive::*;
+use valence::client::movement::*;
+use valence::client::packet::structure_block::*;
+use valence::client::packet::*;
+use valence::client::resource_pack::*;
+use valence::client::settings::*;
+use valence::client::status::*;
+use valence::client::teleport::*;
+use valence::client::title::*;
+use valence::entity::packet::*;
+use valence::instance::packet::*;
+use valence::inventory::packet::synchronize_recipes::*;
+use valence::inventory::packet::*;
+use valence::network::packet::*;
+use valence::particle::*;
+use valence::player_list::packet::*;
+use valence::protocol::packet::boss_bar::*;
+use valence::protocol::packet::chat::*;
+use valence::protocol::packet::command::*;
+use valence::protocol::packet::map::*;
+use valence::protocol::packet::scoreboard::*;
+use valence::protocol::packet::sound::*;
+use valence::registry::tags::*;
+use valence::world_border::packet::*;
+
+use crate::{
+    fields::{Field, FieldReader},
+    packet_registry::Packet as ProxyPacket,
+    schema::PacketSchema,
+};
+
+include!(concat!(env!("OUT_DIR"), "/decode.rs"));
+
+/// A successfully decoded packet body.
+#[derive(Clone, Debug)]
+pub struct Decoded {
+    /// The packet's pretty printed `Debug` output
+    pub text: String,
+    /// Bytes the decoder left in the body, usually a sign that our packet definition differs
+    /// from the server's
+    pub trailing: Bytes,
+}
+
+/// Why a packet body couldn't be decoded, and how far the decoder got.
+#[derive(Clone, Debug)]
+pub struct DecodeError {
+    /// The decoder's error, with its causes
+    pub message: String,
+    /// Position in the body where decoding stopped
+    pub offset: usize,
+    /// The body from `offset` on
+    pub remaining: Bytes,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (stopped at byte {}, {} bytes left)",
+            self.message,
+            self.offset,
+            self.remaining.len()
+        )
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes the body of a packet in [`crate::STD_PACKETS`], or one with a field schema from a
+/// definitions file, and pretty prints its fields.
+///
+/// Returns `None` if there is no decoder for the packet, which includes every packet without a
+/// schema of a session using another protocol version.
+pub fn decode_packet(packet: &ProxyPacket) -> Option<Result<Decoded, DecodeError>> {
+    let data = packet.data.as_deref().unwrap_or_default();
+
+    if let Some(schema) = &packet.schema {
+        return Some(
+            schema
+                .decode(packet.name, data)
+                .map(|(root, consumed)| Decoded {
+                    text: crate::schema::to_debug(&root),
+                    trailing: Bytes::copy_from_slice(&data[consumed..]),
+                }),
+        );
+    }
+
+    decode_std(packet, true).map(|res| {
+        res.map(|decoded| Decoded {
+            text: decoded.text.unwrap_or_default(),
+            trailing: Bytes::copy_from_slice(&data[decoded.consumed()..]),
+        })
+    })
+}
+
+/// Decodes a packet into a tree of its fields, with their position in the body. The root spans
+/// the bytes the decoder consumed, trailing bytes aren't part of the tree.
+///
+/// Returns `None` if there is no decoder for the packet.
+pub fn decode_fields(packet: &ProxyPacket) -> Option<Result<Field, DecodeError>> {
+    decode_fields_as(packet, packet.name, packet.schema.as_deref())
+}
+
+/// Like [`decode_fields`], for `packet` named `name` and laid out by `schema` instead of its
+/// own.
+pub(crate) fn decode_fields_as(
+    packet: &ProxyPacket,
+    name: &str,
+    schema: Option<&PacketSchema>,
+) -> Option<Result<Field, DecodeError>> {
+    let data = packet.data.as_deref().unwrap_or_default();
+
+    if let Some(schema) = schema {
+        return Some(schema.decode(name, data).map(|(root, _)| root));
+    }
+
+    decode_std(packet, false).map(|res| {
+        res.map(|decoded| {
+            let mut root = decoded.root;
+            if matches!(name, "CustomPayloadC2s" | "CustomPayloadS2c") {
+                let consumed = root.len.unwrap_or_default();
+                crate::channels::decode_payload(&mut root, &data[..consumed]);
+            }
+
+            root
+        })
+    })
+}
+
+/// A packet decoded with valence's types.
+struct StdDecoded {
+    root: Field,
+    /// The packet's pretty printed `Debug` output, if it was asked for
+    text: Option<String>,
+}
+
+impl StdDecoded {
+    /// Bytes of the body the decoder read.
+    fn consumed(&self) -> usize {
+        self.root.len.unwrap_or_default()
+    }
+}
+
+/// Decodes a packet of [`crate::STD_PACKETS`] with valence's types, pretty printing it only if
+/// `text` is set.
+fn decode_std(packet: &ProxyPacket, text: bool) -> Option<Result<StdDecoded, DecodeError>> {
+    if !is_std_version(packet) {
+        return None;
+    }
+
+    let data = packet.data.as_deref().unwrap_or_default();
+    decode_std_fields(packet.side, packet.state, packet.id, data, text)
+}
+
+/// Whether valence's types fit the packet, the same id is a different packet in other versions.
+fn is_std_version(packet: &ProxyPacket) -> bool {
+    let version = packet.session.as_ref().and_then(|s| s.protocol_version());
+    version.map_or(true, |v| v == crate::STD_PROTOCOL_VERSION)
+}
+
+/// One field of a packet, as encoded in its body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldLayout {
+    pub name: String,
+    /// Type of the field, named like in [`Field::ty`] for valence's packets, or the
+    /// [`SchemaType`](crate::SchemaType) from a definitions file
+    pub ty: String,
+}
+
+/// The fields of a packet in the order they are encoded, from its schema if it has one, or
+/// from valence's type for packets of [`crate::STD_PACKETS`].
+///
+/// Returns `None` if the fields aren't known, like for packets that are enums in valence.
+pub fn packet_layout(packet: &ProxyPacket) -> Option<Vec<FieldLayout>> {
+    if let Some(schema) = &packet.schema {
+        return Some(
+            schema
+                .fields
+                .iter()
+                .map(|field| FieldLayout {
+                    name: field.name.clone(),
+                    ty: field.ty.name().to_string(),
+                })
+                .collect(),
+        );
+    }
+
+    if !is_std_version(packet) {
+        return None;
+    }
+
+    let fields = std_layout(packet.side, packet.state, packet.id)?;
+    Some(
+        fields
+            .iter()
+            .map(|(name, ty)| FieldLayout {
+                name: name.to_string(),
+                ty: ty.to_string(),
+            })
+            .collect(),
+    )
+}
+
+/// Like [`decode_packet`], with failures described in the returned text.
+pub fn packet_to_string(packet: &ProxyPacket) -> String {
+    match decode_packet(packet) {
+        Some(Ok(decoded)) if decoded.trailing.is_empty() => decoded.text,
+        Some(Ok(decoded)) => format!(
+            "{}\n// {} trailing bytes not consumed by the decoder",
+            decoded.text,
+            decoded.trailing.len()
+        ),
+        Some(Err(e)) => format!("Failed to decode: {}", e),
+        None => "Not yet implemented".to_string(),
+    }
+}
+
+/// Decodes a packet through `read`, which reads its fields from a [`FieldReader`] one by one.
+fn read_fields<'a, P>(
+    data: &'a [u8],
+    name: &str,
+    text: bool,
+    read: impl FnOnce(&mut FieldReader<'a>) -> anyhow::Result<P>,
+) -> Result<StdDecoded, DecodeError>
+where
+    P: Packet + Debug,
+{
+    let mut reader = FieldReader::new(data);
+
+    match read(&mut reader) {
+        Ok(packet) => {
+            let (mut root, _) = reader.finish(name);
+            if root.children.is_empty() {
+                root.value = format!("{:?}", packet);
+            }
+
+            Ok(StdDecoded {
+                root,
+                text: text.then(|| format!("{:#?}", packet)),
+            })
+        }
+        Err(e) => {
+            let offset = reader.offset();
+            Err(DecodeError {
+                message: format!("{:#}", e),
+                offset,
+                remaining: Bytes::copy_from_slice(&data[offset..]),
+            })
+        }
+    }
+}
+
+/// Decodes a packet whose fields build.rs couldn't find, like enums and packets with their own
+/// `Decode` impl, as one value.
+fn read_whole<'a, P>(data: &'a [u8], name: &str, text: bool) -> Result<StdDecoded, DecodeError>
+where
+    P: Packet + Decode<'a> + Debug,
+{
+    read_fields(data, name, text, |r| r.read_all::<P>())
+}
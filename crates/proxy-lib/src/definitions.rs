@@ -0,0 +1,182 @@
+//! Packet names supplied at runtime, for custom or modded packets and snapshot ids the bundled
+//! tables don't know about.
+//!
+//! Definitions files use the format of the bundled tables in `assets/packets/`:
+//!
+//! ```json
+//! [{ "name": "MyModSyncS2c", "side": "clientbound", "state": "play", "id": 123 }]
+//! ```
+//!
+//! Definitions may also describe the packet's fields, see [`PacketSchema`](crate::PacketSchema).
+
+use std::{
+    collections::HashSet,
+    path::Path,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{
+    packet_registry::{overlay_packets, Packet, PacketSide, PacketState},
+    schema::PacketSchema,
+};
+
+#[derive(Deserialize)]
+struct Definition {
+    name: String,
+    side: String,
+    state: String,
+    id: i32,
+    #[serde(default)]
+    fields: Option<PacketSchema>,
+}
+
+/// Packets to add to, or rename in, the table of one protocol version.
+#[derive(Clone, Debug)]
+pub struct PacketDefinitions {
+    pub protocol_version: i32,
+    pub packets: Vec<Packet>,
+}
+
+impl PacketDefinitions {
+    pub fn load(path: &Path, protocol_version: i32) -> anyhow::Result<Self> {
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        Self::parse(&json, protocol_version)
+    }
+
+    pub fn parse(json: &str, protocol_version: i32) -> anyhow::Result<Self> {
+        let definitions: Vec<Definition> =
+            serde_json::from_str(json).context("invalid packet definitions")?;
+
+        let packets = definitions
+            .into_iter()
+            .enumerate()
+            .map(|(i, def)| {
+                to_packet(def).with_context(|| format!("invalid packet definition {}", i + 1))
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        Ok(Self {
+            protocol_version,
+            packets,
+        })
+    }
+
+    /// The packet if it is defined here.
+    pub fn find(&self, side: PacketSide, state: PacketState, id: i32) -> Option<&Packet> {
+        self.packets
+            .iter()
+            .find(|p| p.side == side && p.state == state && p.id == id)
+    }
+
+    /// `packets` with these definitions laid over them, the way
+    /// [`Proxy::with_packet_definitions`](crate::Proxy::with_packet_definitions) names the
+    /// packets of sessions: defined packets replace the ones with the same side, state and id,
+    /// the rest are added.
+    pub fn overlay(&self, packets: &[Packet]) -> Vec<Packet> {
+        let mut packets = packets.to_vec();
+        overlay_packets(&mut packets, &self.packets);
+        packets
+    }
+}
+
+fn to_packet(def: Definition) -> anyhow::Result<Packet> {
+    let side = match def.side.as_str() {
+        "clientbound" => PacketSide::Clientbound,
+        "serverbound" => PacketSide::Serverbound,
+        side => bail!("unknown side \"{side}\""),
+    };
+
+    let state = match def.state.as_str() {
+        "handshaking" => PacketState::Handshaking,
+        "status" => PacketState::Status,
+        "login" => PacketState::Login,
+        "play" => PacketState::Play,
+        state => bail!("unknown state \"{state}\""),
+    };
+
+    Ok(Packet {
+        side,
+        state,
+        id: def.id,
+        timestamp: None,
+        name: intern(def.name),
+        data: None,
+        session: None,
+        schema: def.fields.map(Arc::new),
+    })
+}
+
+/// Packet names have to be `'static` like the bundled ones, so they are leaked, but only once
+/// per distinct name however often definitions are loaded again.
+fn intern(name: String) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+    let mut names = NAMES.get_or_init(Default::default).lock().unwrap();
+    if let Some(&interned) = names.get(name.as_str()) {
+        return interned;
+    }
+
+    let name: &'static str = Box::leak(name.into_boxed_str());
+    names.insert(name);
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = r#"[
+        { "name": "MyModSyncS2c", "side": "clientbound", "state": "play", "id": 123 }
+    ]"#;
+
+    #[test]
+    fn names_are_leaked_once() {
+        let first = PacketDefinitions::parse(JSON, 763).unwrap();
+        let second = PacketDefinitions::parse(JSON, 763).unwrap();
+
+        assert_eq!(first.packets[0].name, "MyModSyncS2c");
+        assert!(std::ptr::eq(first.packets[0].name, second.packets[0].name));
+    }
+
+    #[test]
+    fn overlay_renames_and_adds() {
+        let json = r#"[
+            { "name": "Renamed", "side": "clientbound", "state": "play", "id": 1 },
+            { "name": "MyModSyncS2c", "side": "clientbound", "state": "play", "id": 123 }
+        ]"#;
+        let definitions = PacketDefinitions::parse(json, 763).unwrap();
+        let bundled = [(0, "A"), (1, "B")].map(|(id, name)| Packet {
+            side: PacketSide::Clientbound,
+            state: PacketState::Play,
+            id,
+            timestamp: None,
+            name,
+            data: None,
+            session: None,
+            schema: None,
+        });
+
+        let names = definitions
+            .overlay(&bundled)
+            .iter()
+            .map(|p| (p.id, p.name))
+            .collect::<Vec<_>>();
+
+        assert_eq!(names, [(0, "A"), (1, "Renamed"), (123, "MyModSyncS2c")]);
+    }
+
+    #[test]
+    fn rejects_unknown_sides() {
+        let json = JSON.replace("clientbound", "sideways");
+        let e = PacketDefinitions::parse(&json, 763).unwrap_err();
+
+        assert_eq!(
+            format!("{:#}", e),
+            "invalid packet definition 1: unknown side \"sideways\""
+        );
+    }
+}
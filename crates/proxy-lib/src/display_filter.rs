@@ -0,0 +1,714 @@
+//! Display filters, expressions picking the packets to show:
+//!
+//! ```text
+//! side == serverbound && name ~ "Move" && len > 100
+//! entity_id == 42 || !(state == play)
+//! ```
+//!
+//! Comparisons are combined with `&&`, `||` and `!` (or `and`, `or` and `not`) and grouped with
+//! parentheses. The left side of a comparison is one of the packet properties
+//!
+//! - `side`: `serverbound` or `clientbound`, also `c2s` and `s2c`
+//! - `state`: `handshaking`, `status`, `login` or `play`
+//! - `id`, `name` and `len`, the length of the body
+//! - `session`: the number of the session the packet belongs to
+//!
+//! or the name of a decoded field, found anywhere in the packet. Nested fields, from definitions
+//! schemas and plugin channel payloads, are named with their path, like `payload.brand`. A field
+//! that appears more than once matches if any of its values do.
+//!
+//! The operators are `==`, `!=`, `<`, `<=`, `>`, `>=` and `~`, which tests if the value contains
+//! the text ignoring ASCII case. A name on its own tests whether the packet has that field.
+//! Values are numbers, quoted strings or bare words.
+
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+use anyhow::{anyhow, bail, Context};
+
+use crate::{
+    packet_registry::{Packet, PacketSide, PacketState},
+    schema::PacketSchema,
+};
+
+/// A parsed display filter, see the [module docs](self) for the syntax.
+#[derive(Clone, Debug)]
+pub struct DisplayFilter {
+    source: String,
+    expr: Expr,
+}
+
+impl DisplayFilter {
+    pub fn parse(source: &str) -> anyhow::Result<Self> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+        };
+
+        let expr = parser.or()?;
+        if let Some((token, at)) = parser.tokens.get(parser.pos) {
+            bail!("unexpected {} at {}", token, at + 1);
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// The text the filter was parsed from.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether `packet` passes the filter. Decodes the packet the first time a decoded field
+    /// is needed.
+    pub fn matches(&self, packet: &Packet) -> bool {
+        self.matches_as(packet, packet.name, packet.schema.as_deref())
+    }
+
+    /// Like [`Self::matches`], for `packet` named `name` and laid out by `schema` instead of its
+    /// own, like a packet of a definitions file.
+    #[cfg_attr(not(feature = "decode"), allow(unused_variables))]
+    pub fn matches_as(&self, packet: &Packet, name: &str, schema: Option<&PacketSchema>) -> bool {
+        let mut cx = EvalContext {
+            packet,
+            name,
+            #[cfg(feature = "decode")]
+            schema,
+            #[cfg(feature = "decode")]
+            decoded: None,
+        };
+
+        self.expr.eval(&mut cx)
+    }
+}
+
+impl FromStr for DisplayFilter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    /// The packet has the field
+    Has(Property),
+    Compare(Property, Op, Value),
+}
+
+#[derive(Clone, Debug)]
+enum Property {
+    Side,
+    State,
+    Id,
+    Name,
+    Len,
+    Session,
+    /// Path of a decoded field
+    Field(Vec<String>),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Contains,
+}
+
+/// The right side of a comparison. Literals that read as numbers compare as numbers against
+/// values that do too.
+#[derive(Clone, Debug)]
+enum Value {
+    Side(PacketSide),
+    State(PacketState),
+    Literal {
+        text: String,
+        number: Option<Number>,
+    },
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Number {
+    Int(i128),
+    Float(f64),
+}
+
+impl Number {
+    fn parse(s: &str) -> Option<Self> {
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s),
+        };
+
+        if let Some(hex) = digits.strip_prefix("0x") {
+            let n = i128::from_str_radix(hex, 16).ok()?;
+            return Some(Self::Int(if negative { -n } else { n }));
+        }
+
+        if let Ok(n) = s.parse() {
+            return Some(Self::Int(n));
+        }
+
+        s.parse()
+            .ok()
+            .filter(|f: &f64| f.is_finite())
+            .map(Self::Float)
+    }
+
+    fn compare(self, other: Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => Some(a.cmp(&b)),
+            (Self::Int(a), Self::Float(b)) => (a as f64).partial_cmp(&b),
+            (Self::Float(a), Self::Int(b)) => a.partial_cmp(&(b as f64)),
+            (Self::Float(a), Self::Float(b)) => a.partial_cmp(&b),
+        }
+    }
+}
+
+struct EvalContext<'a> {
+    packet: &'a Packet,
+    name: &'a str,
+    #[cfg(feature = "decode")]
+    schema: Option<&'a PacketSchema>,
+    /// Decoded fields of the packet once they were needed, `None` inside if it doesn't decode
+    #[cfg(feature = "decode")]
+    decoded: Option<Option<crate::fields::Field>>,
+}
+
+impl Expr {
+    fn eval(&self, cx: &mut EvalContext) -> bool {
+        match self {
+            Self::And(a, b) => a.eval(cx) && b.eval(cx),
+            Self::Or(a, b) => a.eval(cx) || b.eval(cx),
+            Self::Not(expr) => !expr.eval(cx),
+            Self::Has(property) => !property.values(cx).is_empty(),
+            // `!=` is the opposite of `==`, also for fields the packet doesn't have
+            Self::Compare(property, Op::Ne, value) => !property
+                .values(cx)
+                .iter()
+                .any(|actual| compare(actual, Op::Eq, value)),
+            Self::Compare(property, op, value) => property
+                .values(cx)
+                .iter()
+                .any(|actual| compare(actual, *op, value)),
+        }
+    }
+}
+
+/// A value of a packet, as text like the decoded fields.
+enum Actual {
+    Side(PacketSide),
+    State(PacketState),
+    Text(String),
+}
+
+impl Property {
+    fn values(&self, cx: &mut EvalContext) -> Vec<Actual> {
+        let packet = cx.packet;
+        match self {
+            Self::Side => vec![Actual::Side(packet.side)],
+            Self::State => vec![Actual::State(packet.state)],
+            Self::Id => vec![Actual::Text(packet.id.to_string())],
+            Self::Name => vec![Actual::Text(cx.name.to_string())],
+            Self::Len => {
+                let len = packet.data.as_ref().map_or(0, |data| data.len());
+                vec![Actual::Text(len.to_string())]
+            }
+            Self::Session => packet
+                .session
+                .iter()
+                .map(|session| Actual::Text(session.id.0.to_string()))
+                .collect(),
+            Self::Field(path) => field_values(cx, path),
+        }
+    }
+}
+
+#[cfg(feature = "decode")]
+fn field_values(cx: &mut EvalContext, path: &[String]) -> Vec<Actual> {
+    use crate::fields::Field;
+
+    fn find<'a>(field: &'a Field, path: &[String], found: &mut Vec<&'a Field>) {
+        for child in &field.children {
+            if child.name == path[0] {
+                descend(child, &path[1..], found);
+            }
+            find(child, path, found);
+        }
+    }
+
+    fn descend<'a>(field: &'a Field, path: &[String], found: &mut Vec<&'a Field>) {
+        let Some((first, rest)) = path.split_first() else {
+            found.push(field);
+            return;
+        };
+
+        for child in field.children.iter().filter(|child| child.name == *first) {
+            descend(child, rest, found);
+        }
+    }
+
+    let (packet, name, schema) = (cx.packet, cx.name, cx.schema);
+    let root = cx.decoded.get_or_insert_with(|| {
+        crate::decode::decode_fields_as(packet, name, schema).and_then(Result::ok)
+    });
+
+    let mut found = Vec::new();
+    if let Some(root) = root {
+        find(root, path, &mut found);
+    }
+
+    found
+        .into_iter()
+        .map(|field| {
+            // fields with children have no value of their own, but still exist
+            Actual::Text(if field.children.is_empty() {
+                field.value.clone()
+            } else {
+                field.ty.clone()
+            })
+        })
+        .collect()
+}
+
+/// Without decoders there are no fields to find.
+#[cfg(not(feature = "decode"))]
+fn field_values(_: &mut EvalContext, _: &[String]) -> Vec<Actual> {
+    Vec::new()
+}
+
+fn compare(actual: &Actual, op: Op, expected: &Value) -> bool {
+    let ordering = match (actual, expected) {
+        // only ever compared with `==`, see `Expr::eval`
+        (Actual::Side(a), Value::Side(b)) => return a == b,
+        (Actual::State(a), Value::State(b)) => return a == b,
+        (Actual::Text(text), Value::Literal { text: literal, .. }) if op == Op::Contains => {
+            return contains_ignore_case(text, literal);
+        }
+        (
+            Actual::Text(text),
+            Value::Literal {
+                text: literal,
+                number,
+            },
+        ) => match number.zip(Number::parse(text)) {
+            Some((expected, actual)) => actual.compare(expected),
+            None => Some(text.as_str().cmp(literal.as_str())),
+        },
+        // the parser only allows sides and states on their own properties
+        _ => return false,
+    };
+
+    let Some(ordering) = ordering else {
+        return false;
+    };
+
+    match op {
+        Op::Eq => ordering.is_eq(),
+        Op::Ne => ordering.is_ne(),
+        Op::Lt => ordering.is_lt(),
+        Op::Le => ordering.is_le(),
+        Op::Gt => ordering.is_gt(),
+        Op::Ge => ordering.is_ge(),
+        Op::Contains => unreachable!(),
+    }
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    needle.is_empty()
+        || haystack
+            .as_bytes()
+            .windows(needle.len())
+            .any(|w| w.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Str(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Word(word) => write!(f, "`{}`", word),
+            Self::Str(s) => write!(f, "{:?}", s),
+            Self::Op(op) => write!(f, "`{}`", op),
+            Self::And => f.write_str("`&&`"),
+            Self::Or => f.write_str("`||`"),
+            Self::Not => f.write_str("`!`"),
+            Self::Open => f.write_str("`(`"),
+            Self::Close => f.write_str("`)`"),
+        }
+    }
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Contains => "~",
+        })
+    }
+}
+
+/// Splits `s` into tokens and the byte offsets they start at.
+fn tokenize(s: &str) -> anyhow::Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+
+    while let Some((at, c)) = chars.next() {
+        let mut next_is = |expected| chars.next_if(|&(_, c)| c == expected).is_some();
+
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '~' => Token::Op(Op::Contains),
+            '&' if next_is('&') => Token::And,
+            '|' if next_is('|') => Token::Or,
+            '=' if next_is('=') => Token::Op(Op::Eq),
+            '!' if next_is('=') => Token::Op(Op::Ne),
+            '!' => Token::Not,
+            '<' if next_is('=') => Token::Op(Op::Le),
+            '<' => Token::Op(Op::Lt),
+            '>' if next_is('=') => Token::Op(Op::Ge),
+            '>' => Token::Op(Op::Gt),
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c)) => text.push(c),
+                            None => bail!("unterminated string at {}", at + 1),
+                        },
+                        Some((_, c)) => text.push(c),
+                        None => bail!("unterminated string at {}", at + 1),
+                    }
+                }
+                Token::Str(text)
+            }
+            c if is_word_char(c) => {
+                let mut end = at + c.len_utf8();
+                while let Some((i, c)) = chars.next_if(|&(_, c)| is_word_char(c)) {
+                    end = i + c.len_utf8();
+                }
+
+                match &s[at..end] {
+                    "and" => Token::And,
+                    "or" => Token::Or,
+                    "not" => Token::Not,
+                    word => Token::Word(word.to_string()),
+                }
+            }
+            c => bail!("unexpected `{}` at {}", c, at + 1),
+        };
+
+        tokens.push((token, at));
+    }
+
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '.' | '-' | '[' | ']')
+}
+
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn next(&mut self, expected: &str) -> anyhow::Result<&'a Token> {
+        let (token, _) = self
+            .tokens
+            .get(self.pos)
+            .with_context(|| format!("expected {} at the end", expected))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn error(&self, expected: &str) -> anyhow::Error {
+        match self.tokens.get(self.pos) {
+            Some((token, at)) => {
+                anyhow!("expected {}, found {} at {}", expected, token, at + 1)
+            }
+            None => anyhow!("expected {} at the end", expected),
+        }
+    }
+
+    fn or(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> anyhow::Result<Expr> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> anyhow::Result<Expr> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                let expr = self.or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(self.error("`)`"));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Some(Token::Word(_)) => self.comparison(),
+            _ => Err(self.error("a field name")),
+        }
+    }
+
+    fn comparison(&mut self) -> anyhow::Result<Expr> {
+        let Token::Word(name) = self.next("a field name")? else {
+            unreachable!();
+        };
+
+        let property = match name.as_str() {
+            "side" => Property::Side,
+            "state" => Property::State,
+            "id" => Property::Id,
+            "name" => Property::Name,
+            "len" => Property::Len,
+            "session" => Property::Session,
+            path => Property::Field(path.split('.').map(str::to_string).collect()),
+        };
+
+        let Some(&Token::Op(op)) = self.peek() else {
+            return Ok(Expr::Has(property));
+        };
+        self.pos += 1;
+
+        let text = match self.peek() {
+            Some(Token::Word(text) | Token::Str(text)) => text,
+            _ => return Err(self.error("a value")),
+        };
+
+        let value = match &property {
+            Property::Side | Property::State if !matches!(op, Op::Eq | Op::Ne) => {
+                bail!("{} can only be compared with `==` and `!=`", name)
+            }
+            Property::Side => Value::Side(parse_side(text)?),
+            Property::State => Value::State(parse_state(text)?),
+            _ => {
+                let number = Number::parse(text);
+                if number.is_none() && !matches!(op, Op::Eq | Op::Ne | Op::Contains) {
+                    bail!("{} needs a number, not \"{}\"", op, text);
+                }
+
+                Value::Literal {
+                    text: text.clone(),
+                    number,
+                }
+            }
+        };
+        self.pos += 1;
+
+        Ok(Expr::Compare(property, op, value))
+    }
+}
+
+fn parse_side(s: &str) -> anyhow::Result<PacketSide> {
+    match s.to_ascii_lowercase().as_str() {
+        "serverbound" | "c2s" => Ok(PacketSide::Serverbound),
+        "clientbound" | "s2c" => Ok(PacketSide::Clientbound),
+        _ => bail!(
+            "unknown side \"{}\", expected serverbound or clientbound",
+            s
+        ),
+    }
+}
+
+fn parse_state(s: &str) -> anyhow::Result<PacketState> {
+    match s.to_ascii_lowercase().as_str() {
+        "handshaking" => Ok(PacketState::Handshaking),
+        "status" => Ok(PacketState::Status),
+        "login" => Ok(PacketState::Login),
+        "play" => Ok(PacketState::Play),
+        _ => bail!("unknown state \"{}\"", s),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn packet(name: &'static str, id: i32, len: usize) -> Packet {
+        Packet {
+            side: PacketSide::Serverbound,
+            state: PacketState::Play,
+            id,
+            timestamp: None,
+            name,
+            data: Some(Bytes::from(vec![0; len])),
+            session: None,
+            schema: None,
+        }
+    }
+
+    fn matches(filter: &str, packet: &Packet) -> bool {
+        DisplayFilter::parse(filter).unwrap().matches(packet)
+    }
+
+    fn error(filter: &str) -> String {
+        DisplayFilter::parse(filter).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let p = packet("A", 1, 0);
+
+        assert!(matches("name == A || name == B && id == 2", &p));
+        assert!(!matches("(name == A || name == B) && id == 2", &p));
+        assert!(matches("name == B and id == 2 or id == 1", &p));
+        assert!(!matches("!name == A || id == 2", &p));
+        assert!(matches("not (name == B || id == 2)", &p));
+    }
+
+    #[test]
+    fn missing_fields() {
+        let p = packet("A", 1, 0);
+
+        assert!(matches("entity_id != 3", &p));
+        assert!(!matches("entity_id == 3", &p));
+        assert!(!matches("entity_id < 3", &p));
+        assert!(!matches("entity_id", &p));
+        assert!(matches("!entity_id", &p));
+    }
+
+    #[test]
+    fn numbers() {
+        let p = packet("A", 0x1f, 4);
+
+        assert!(matches("id == 0x1f", &p));
+        assert!(matches("id == 31", &p));
+        assert!(matches("id > -1 && id >= -0x20", &p));
+        assert!(matches("len <= 4 && len > 3.5", &p));
+        assert!(!matches("id < 0x10", &p));
+        // compared as numbers, not as text
+        assert!(matches("id > 4", &p));
+        assert!(matches("id == 031", &p));
+
+        let negative = packet("A", -2, 0);
+        assert!(matches("id == -2 && id == -0x2 && id < -1", &negative));
+    }
+
+    #[test]
+    fn contains_ignores_case() {
+        let p = packet("MovePlayerPosC2s", 0x14, 0);
+
+        assert!(matches("name ~ move", &p));
+        assert!(matches("name ~ \"PLAYERpos\"", &p));
+        assert!(matches("name ~ \"\"", &p));
+        assert!(!matches("name ~ look", &p));
+        assert!(matches("id ~ 2", &packet("A", 20, 0)));
+    }
+
+    #[test]
+    fn sides_and_states() {
+        let p = packet("A", 1, 0);
+
+        assert!(matches("side == c2s && side != clientbound", &p));
+        assert!(matches("state == PLAY", &p));
+        assert!(!matches("state != play", &p));
+    }
+
+    #[test]
+    fn matches_as_overrides_name() {
+        let p = packet("A", 1, 0);
+        let filter = DisplayFilter::parse("name == Defined").unwrap();
+
+        assert!(!filter.matches(&p));
+        assert!(filter.matches_as(&p, "Defined", None));
+    }
+
+    #[cfg(feature = "decode")]
+    #[test]
+    fn matches_as_decodes_with_schema() {
+        use crate::schema::{SchemaField, SchemaType};
+
+        let mut p = packet("A", 1, 0);
+        p.data = Some(Bytes::from_static(&[0, 0, 0, 42]));
+        let schema = PacketSchema {
+            fields: vec![SchemaField {
+                name: "count".to_string(),
+                ty: SchemaType::Int,
+            }],
+        };
+        let filter = DisplayFilter::parse("count == 42").unwrap();
+
+        assert!(!filter.matches(&p));
+        assert!(filter.matches_as(&p, "A", Some(&schema)));
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(error("id = 3"), "unexpected `=` at 4");
+        assert_eq!(error("id == 1 )"), "unexpected `)` at 9");
+        assert_eq!(error("(id == 1"), "expected `)` at the end");
+        assert_eq!(error("id == 1 &&"), "expected a field name at the end");
+        assert_eq!(error("&& id"), "expected a field name, found `&&` at 1");
+        assert_eq!(error("id == && 1"), "expected a value, found `&&` at 7");
+        assert_eq!(error("id =="), "expected a value at the end");
+        assert_eq!(error("name == \"abc"), "unterminated string at 9");
+    }
+
+    #[test]
+    fn value_errors() {
+        assert_eq!(error("len > abc"), "> needs a number, not \"abc\"");
+        assert_eq!(
+            error("side ~ c2s"),
+            "side can only be compared with `==` and `!=`"
+        );
+        assert_eq!(
+            error("side == up"),
+            "unknown side \"up\", expected serverbound or clientbound"
+        );
+        assert_eq!(error("state == config"), "unknown state \"config\"");
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Mutex;
+
+use anyhow::{bail, Context};
+use rand::Rng;
+use rsa::{
+    pkcs8::{DecodePublicKey, EncodePublicKey},
+    PaddingScheme, PublicKey, RsaPrivateKey, RsaPublicKey,
+};
+use valence_core::protocol::decode::PacketFrame;
+use valence_network::packet::{LoginHelloS2c, LoginKeyC2s};
+
+use crate::encode_frame;
+
+/// The keypair the proxy presents to clients in place of the server's.
+pub(crate) struct ProxyKeys {
+    private_key: RsaPrivateKey,
+    public_key_der: Vec<u8>,
+}
+
+impl ProxyKeys {
+    pub(crate) fn generate() -> anyhow::Result<Self> {
+        // same key size the vanilla server uses
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024)?;
+        let public_key_der = RsaPublicKey::from(&private_key)
+            .to_public_key_der()?
+            .into_vec();
+
+        Ok(Self {
+            private_key,
+            public_key_der,
+        })
+    }
+}
+
+struct ServerHandshake {
+    public_key: RsaPublicKey,
+    verify_token: Vec<u8>,
+    shared_secret: [u8; 16],
+}
+
+/// Encryption state of one session, shared between both relay directions.
+pub(crate) struct SessionCrypto {
+    server: Option<ServerHandshake>,
+    /// Token we asked the client to send back
+    client_verify_token: [u8; 4],
+    /// Secret the client picked, still to be applied to the writer towards the client
+    pending_client_secret: Option<[u8; 16]>,
+}
+
+impl SessionCrypto {
+    pub(crate) fn new() -> Mutex<Self> {
+        Mutex::new(Self {
+            server: None,
+            client_verify_token: rand::thread_rng().gen(),
+            pending_client_secret: None,
+        })
+    }
+
+    pub(crate) fn take_pending_client_secret(&mut self) -> Option<[u8; 16]> {
+        self.pending_client_secret.take()
+    }
+}
+
+/// Handles the server's encryption request. Returns the request to send to the client instead
+/// and the secret the server stream is encrypted with from now on.
+pub(crate) fn intercept_server_hello(
+    keys: &ProxyKeys,
+    crypto: &Mutex<SessionCrypto>,
+    hello: &LoginHelloS2c,
+) -> anyhow::Result<(PacketFrame, [u8; 16])> {
+    let public_key = RsaPublicKey::from_public_key_der(hello.public_key)
+        .context("server sent an invalid public key")?;
+    let shared_secret: [u8; 16] = rand::thread_rng().gen();
+
+    let mut crypto = crypto.lock().unwrap();
+    crypto.server = Some(ServerHandshake {
+        public_key,
+        verify_token: hello.verify_token.to_vec(),
+        shared_secret,
+    });
+
+    let frame = encode_frame(&LoginHelloS2c {
+        server_id: hello.server_id,
+        public_key: &keys.public_key_der,
+        verify_token: &crypto.client_verify_token,
+    })?;
+
+    Ok((frame, shared_secret))
+}
+
+/// Handles the client's encryption response. Returns the response to send to the server
+/// instead, the secret the client picked and the secret we picked for the server.
+pub(crate) fn intercept_client_key(
+    keys: &ProxyKeys,
+    crypto: &Mutex<SessionCrypto>,
+    key: &LoginKeyC2s,
+) -> anyhow::Result<(PacketFrame, [u8; 16], [u8; 16])> {
+    let mut crypto = crypto.lock().unwrap();
+
+    let verify_token = keys
+        .private_key
+        .decrypt(PaddingScheme::new_pkcs1v15_encrypt(), key.verify_token)
+        .context("failed to decrypt client verify token")?;
+
+    if verify_token != crypto.client_verify_token {
+        bail!("client verify token does not match");
+    }
+
+    let client_secret: [u8; 16] = keys
+        .private_key
+        .decrypt(PaddingScheme::new_pkcs1v15_encrypt(), key.shared_secret)
+        .context("failed to decrypt client shared secret")?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("client shared secret has the wrong length"))?;
+
+    let Some(server) = &crypto.server else {
+        bail!("client sent an encryption response without a request");
+    };
+
+    let mut rng = rand::thread_rng();
+    let shared_secret = server.public_key.encrypt(
+        &mut rng,
+        PaddingScheme::new_pkcs1v15_encrypt(),
+        &server.shared_secret,
+    )?;
+    let verify_token = server.public_key.encrypt(
+        &mut rng,
+        PaddingScheme::new_pkcs1v15_encrypt(),
+        &server.verify_token,
+    )?;
+
+    let frame = encode_frame(&LoginKeyC2s {
+        shared_secret: &shared_secret,
+        verify_token: &verify_token,
+    })?;
+    let server_secret = server.shared_secret;
+
+    crypto.pending_client_secret = Some(client_secret);
+
+    Ok((frame, client_secret, server_secret))
+}
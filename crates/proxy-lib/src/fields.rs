@@ -0,0 +1,278 @@
+//! The tree of fields of a decoded packet, with where each field sits in the packet body.
+
+use std::{any::type_name, fmt::Debug};
+
+use valence::protocol::Decode;
+
+/// One node of a decoded packet.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Field {
+    /// Field name, `[n]` for list items and `n` for tuple items
+    pub name: String,
+    /// Type name without module paths, like `VarInt` or `Option<Text>`, or the type from a
+    /// definitions schema
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub ty: String,
+    /// Empty for fields with children
+    pub value: String,
+    /// Position in the packet body, `None` where it couldn't be worked out
+    pub offset: Option<usize>,
+    pub len: Option<usize>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Vec::is_empty"))]
+    pub children: Vec<Field>,
+}
+
+impl Field {
+    pub(crate) fn leaf(name: String, ty: &str, value: String) -> Self {
+        Self {
+            name,
+            ty: ty.to_string(),
+            value,
+            offset: None,
+            len: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub(crate) fn node(name: String, ty: &str, children: Vec<Field>) -> Self {
+        Self {
+            name,
+            ty: ty.to_string(),
+            value: String::new(),
+            offset: None,
+            len: None,
+            children,
+        }
+    }
+
+    /// Byte range in the packet body, if known.
+    pub fn range(&self) -> Option<std::ops::Range<usize>> {
+        Some(self.offset?..self.offset? + self.len?)
+    }
+
+    /// The innermost field covering the byte at `offset` of the body.
+    pub fn find_at(&self, offset: usize) -> Option<&Field> {
+        if !self.range()?.contains(&offset) {
+            return None;
+        }
+
+        self.children
+            .iter()
+            .find_map(|child| child.find_at(offset))
+            .or(Some(self))
+    }
+}
+
+/// Decodes a packet body field by field, recording where each field starts and ends. The code
+/// generated by build.rs reads every field of a packet struct through [`FieldReader::read`], in
+/// declaration order, exactly as the derived `Decode` impl would.
+pub(crate) struct FieldReader<'a> {
+    body: &'a [u8],
+    rest: &'a [u8],
+    fields: Vec<Field>,
+}
+
+impl<'a> FieldReader<'a> {
+    pub(crate) fn new(body: &'a [u8]) -> Self {
+        Self {
+            body,
+            rest: body,
+            fields: Vec::new(),
+        }
+    }
+
+    /// Position of the next field in the body.
+    pub(crate) fn offset(&self) -> usize {
+        self.body.len() - self.rest.len()
+    }
+
+    /// Decodes the next field, named `name`.
+    pub(crate) fn read<T>(&mut self, name: &str) -> anyhow::Result<T>
+    where
+        T: Decode<'a> + Debug,
+    {
+        let start = self.offset();
+        let value = T::decode(&mut self.rest)?;
+
+        let ty = short_type_name(type_name::<T>());
+        let mut field = Field::leaf(name.to_string(), &ty, value_text(&value, &ty));
+        field.offset = Some(start);
+        field.len = Some(self.offset() - start);
+        self.fields.push(field);
+
+        Ok(value)
+    }
+
+    /// Decodes the rest of the packet as one value, for packets whose fields aren't known.
+    pub(crate) fn read_all<T>(&mut self) -> anyhow::Result<T>
+    where
+        T: Decode<'a>,
+    {
+        T::decode(&mut self.rest)
+    }
+
+    /// The fields read so far under a root named after the packet, spanning the bytes read, and
+    /// the bytes left in the body.
+    pub(crate) fn finish(self, packet_name: &str) -> (Field, &'a [u8]) {
+        let consumed = self.offset();
+        let mut root = Field::node(String::new(), packet_name, self.fields);
+        root.offset = Some(0);
+        root.len = Some(consumed);
+
+        (root, self.rest)
+    }
+}
+
+/// A type name without module paths, e.g. `Option<valence_core::text::Text>` becomes
+/// `Option<Text>`.
+fn short_type_name(full: &str) -> String {
+    let mut name = String::new();
+    let mut ident_start = 0;
+    let mut rest = full;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("::") {
+            name.truncate(ident_start);
+            rest = after;
+            continue;
+        }
+
+        name.push(c);
+        if !(c.is_alphanumeric() || c == '_') {
+            ident_start = name.len();
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    name
+}
+
+/// The compact `Debug` output of a field, without the quotes of strings and the wrapper of
+/// newtypes like `VarInt(5)`, so filters can compare against the plain value.
+fn value_text(value: &impl Debug, ty: &str) -> String {
+    let text = format!("{:?}", value);
+
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        return text[1..text.len() - 1]
+            .replace("\\\"", "\"")
+            .replace("\\\\", "\\");
+    }
+
+    let ty = ty.split('<').next().unwrap_or(ty);
+    match text
+        .strip_prefix(ty)
+        .and_then(|t| t.strip_prefix('('))
+        .and_then(|t| t.strip_suffix(')'))
+    {
+        Some(inner) if !inner.contains(['(', ',']) => inner.to_string(),
+        _ => text,
+    }
+}
+
+/// Reads a VarInt or VarLong, returning its raw bits and encoded length.
+pub(crate) fn read_var_int(bytes: &[u8]) -> Option<(u64, usize)> {
+    let mut raw = 0u64;
+
+    for (i, &b) in bytes.iter().enumerate().take(10) {
+        raw |= ((b & 0x7f) as u64) << (i * 7);
+        if b & 0x80 == 0 {
+            return Some((raw, i + 1));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+    use crate::{
+        decode::decode_fields,
+        packet_registry::{Packet, PacketSide, PacketState},
+    };
+
+    #[derive(Debug)]
+    struct Wrapper<T>(T);
+
+    #[test]
+    fn reads_var_ints() {
+        assert_eq!(read_var_int(&[0x00]), Some((0, 1)));
+        assert_eq!(read_var_int(&[0xfb, 0x05, 0xff]), Some((763, 2)));
+        assert_eq!(
+            read_var_int(&[0xff, 0xff, 0xff, 0xff, 0x0f]),
+            Some((u32::MAX as u64, 5))
+        );
+        assert_eq!(read_var_int(&[0x80]), None);
+        assert_eq!(read_var_int(&[0x80; 11]), None);
+    }
+
+    #[test]
+    fn short_type_names() {
+        assert_eq!(
+            short_type_name("valence_core::protocol::var_int::VarInt"),
+            "VarInt"
+        );
+        assert_eq!(
+            short_type_name("core::option::Option<valence_core::text::Text>"),
+            "Option<Text>"
+        );
+        assert_eq!(
+            short_type_name("(u8, alloc::string::String)"),
+            "(u8, String)"
+        );
+        assert_eq!(short_type_name("&[u8]"), "&[u8]");
+    }
+
+    #[test]
+    fn value_texts() {
+        assert_eq!(value_text(&"say \"hi\"", "&str"), "say \"hi\"");
+        assert_eq!(value_text(&Wrapper(5), "Wrapper<i32>"), "5");
+        assert_eq!(
+            value_text(&Wrapper((1, 2)), "Wrapper<(i32, i32)>"),
+            "Wrapper((1, 2))"
+        );
+        assert_eq!(value_text(&Some(3), "Option<i32>"), "Some(3)");
+        assert_eq!(value_text(&[1, 2], "[i32; 2]"), "[1, 2]");
+    }
+
+    #[test]
+    fn handshake_fields() {
+        let body = [&[0xfb, 0x05, 9][..], b"localhost", &[0x63, 0xdd, 2]].concat();
+        let packet = Packet {
+            side: PacketSide::Serverbound,
+            state: PacketState::Handshaking,
+            id: 0,
+            timestamp: None,
+            name: "HandshakeC2s",
+            data: Some(Bytes::from(body.clone())),
+            session: None,
+            schema: None,
+        };
+
+        let root = decode_fields(&packet).unwrap().unwrap();
+
+        assert_eq!(root.ty, "HandshakeC2s");
+        assert_eq!(root.range(), Some(0..15));
+        let fields = root
+            .children
+            .iter()
+            .map(|f| (f.name.as_str(), f.range().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            [
+                ("protocol_version", 0..2),
+                ("server_address", 2..12),
+                ("server_port", 12..14),
+                ("next_state", 14..15),
+            ]
+        );
+        assert_eq!(root.children[0].value, "763");
+        assert_eq!(&body[root.children[1].range().unwrap()][1..], b"localhost");
+        assert_eq!(root.children[2].value, "25565");
+        assert_eq!(root.find_at(13), Some(&root.children[2]));
+    }
+}
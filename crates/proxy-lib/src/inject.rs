@@ -0,0 +1,81 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{anyhow, bail};
+use bytes::BytesMut;
+use valence_core::protocol::decode::PacketFrame;
+
+use crate::{packet_registry::PacketSide, session::SessionId};
+
+struct InjectSenders {
+    to_server: flume::Sender<PacketFrame>,
+    to_client: flume::Sender<PacketFrame>,
+}
+
+/// Packets waiting to be written into one session, read by its relay loops.
+pub(crate) struct InjectQueue {
+    pub(crate) to_server: flume::Receiver<PacketFrame>,
+    pub(crate) to_client: flume::Receiver<PacketFrame>,
+}
+
+/// Sends packets into the live sessions of a [`crate::Proxy`].
+///
+/// Injected packets are written with the session's current compression threshold and
+/// encryption, and show up as regular packets for subscribers.
+#[derive(Clone, Default)]
+pub struct Injector {
+    sessions: Arc<Mutex<HashMap<SessionId, InjectSenders>>>,
+}
+
+impl Injector {
+    pub(crate) fn register(&self, session: SessionId) -> InjectQueue {
+        let (to_server, to_server_queue) = flume::unbounded();
+        let (to_client, to_client_queue) = flume::unbounded();
+
+        self.sessions.lock().unwrap().insert(
+            session,
+            InjectSenders {
+                to_server,
+                to_client,
+            },
+        );
+
+        InjectQueue {
+            to_server: to_server_queue,
+            to_client: to_client_queue,
+        }
+    }
+
+    pub(crate) fn unregister(&self, session: SessionId) {
+        self.sessions.lock().unwrap().remove(&session);
+    }
+
+    /// Queues a packet with the given id and uncompressed body. `side` is the direction it
+    /// travels in, so [`PacketSide::Serverbound`] sends it to the server.
+    pub fn inject(
+        &self,
+        session: SessionId,
+        side: PacketSide,
+        id: i32,
+        body: &[u8],
+    ) -> anyhow::Result<()> {
+        let sessions = self.sessions.lock().unwrap();
+        let Some(senders) = sessions.get(&session) else {
+            bail!("session {} is not open", session);
+        };
+
+        let sender = match side {
+            PacketSide::Serverbound => &senders.to_server,
+            PacketSide::Clientbound => &senders.to_client,
+        };
+
+        sender
+            .send(PacketFrame {
+                id,
+                body: BytesMut::from(body),
+            })
+            .map_err(|_| anyhow!("session {} is closing", session))
+    }
+}
@@ -0,0 +1,252 @@
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use tokio::sync::oneshot;
+use valence_core::protocol::decode::PacketFrame;
+
+use crate::{
+    packet_registry::{PacketSide, PacketState},
+    session::Session,
+};
+
+/// What the relay loop knows about a packet it is about to forward.
+pub struct InterceptContext<'a> {
+    pub session: &'a Arc<Session>,
+    /// Direction the packet is travelling in
+    pub side: PacketSide,
+    pub state: PacketState,
+    /// Name from the proxy's packet table, "Unknown Packet" if it isn't in there
+    pub name: &'static str,
+    /// Compression threshold the frames will be written with
+    pub threshold: Option<u32>,
+}
+
+pub enum Verdict {
+    /// Forward the packet unchanged
+    Pass,
+    /// Don't forward the packet
+    Drop,
+    /// Forward the packet with a different body
+    Rewrite(BytesMut),
+    /// Forward these frames instead, include the original to inject around it
+    Replace(Vec<PacketFrame>),
+    /// Hold the packet, and with it this direction of the session, until the final verdict
+    /// arrives. Treated as [`Verdict::Pass`] when the sender is dropped.
+    Hold(oneshot::Receiver<Verdict>),
+}
+
+/// Inspects every packet before it is forwarded and decides what gets sent on.
+///
+/// Connection state tracking always follows the packets as they were received, so dropping or
+/// rewriting e.g. `LoginCompressionS2c` leaves the proxy and the peer disagreeing.
+pub trait Interceptor: Send + Sync {
+    fn intercept(&self, ctx: &InterceptContext, frame: &PacketFrame) -> Verdict;
+}
+
+/// Runs `frame` through every interceptor in order, returning the frames to forward.
+pub(crate) async fn run_interceptors(
+    interceptors: &[Arc<dyn Interceptor>],
+    ctx: &InterceptContext<'_>,
+    frame: PacketFrame,
+) -> Vec<PacketFrame> {
+    let mut frames = vec![frame];
+
+    for interceptor in interceptors {
+        let mut next = Vec::with_capacity(frames.len());
+
+        for frame in frames {
+            let mut verdict = interceptor.intercept(ctx, &frame);
+            while let Verdict::Hold(receiver) = verdict {
+                verdict = receiver.await.unwrap_or(Verdict::Pass);
+            }
+
+            match verdict {
+                Verdict::Pass => next.push(frame),
+                Verdict::Drop => {}
+                Verdict::Rewrite(body) => next.push(PacketFrame { id: frame.id, body }),
+                Verdict::Replace(frames) => next.extend(frames),
+                Verdict::Hold(_) => unreachable!(),
+            }
+        }
+
+        frames = next;
+    }
+
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, OnceLock};
+
+    use time::OffsetDateTime;
+
+    use super::*;
+    use crate::{connection_state::ConnectionState, session::SessionId};
+
+    /// Interceptor deciding with a closure
+    struct Rule<F>(F);
+
+    impl<F: Fn(&PacketFrame) -> Verdict + Send + Sync> Interceptor for Rule<F> {
+        fn intercept(&self, _ctx: &InterceptContext, frame: &PacketFrame) -> Verdict {
+            (self.0)(frame)
+        }
+    }
+
+    /// Interceptor remembering the frames it saw and passing them on
+    #[derive(Default)]
+    struct Seen(Mutex<Vec<(i32, Vec<u8>)>>);
+
+    impl Interceptor for Seen {
+        fn intercept(&self, _ctx: &InterceptContext, frame: &PacketFrame) -> Verdict {
+            self.0.lock().unwrap().push(contents(frame));
+            Verdict::Pass
+        }
+    }
+
+    fn frame(id: i32, body: &[u8]) -> PacketFrame {
+        PacketFrame {
+            id,
+            body: BytesMut::from(body),
+        }
+    }
+
+    fn contents(frame: &PacketFrame) -> (i32, Vec<u8>) {
+        (frame.id, frame.body.to_vec())
+    }
+
+    /// Runs `frame` through `interceptors` as the relay loop would
+    async fn run(interceptors: &[Arc<dyn Interceptor>], frame: PacketFrame) -> Vec<(i32, Vec<u8>)> {
+        let session = Arc::new(Session {
+            id: SessionId(1),
+            client_addr: "127.0.0.1:50000".parse().unwrap(),
+            server_addr: "127.0.0.1:25565".parse().unwrap(),
+            accepted_at: OffsetDateTime::UNIX_EPOCH,
+            protocol_version: OnceLock::new(),
+            connection: Arc::new(ConnectionState::new()),
+        });
+        let ctx = InterceptContext {
+            session: &session,
+            side: PacketSide::Serverbound,
+            state: PacketState::Play,
+            name: "ChatMessageC2s",
+            threshold: None,
+        };
+
+        run_interceptors(interceptors, &ctx, frame)
+            .await
+            .iter()
+            .map(contents)
+            .collect()
+    }
+
+    /// Interceptor holding the first frame it sees until `receiver` decides
+    fn hold(receiver: oneshot::Receiver<Verdict>) -> Arc<dyn Interceptor> {
+        let receiver = Mutex::new(Some(receiver));
+        Arc::new(Rule(move |_: &PacketFrame| {
+            Verdict::Hold(receiver.lock().unwrap().take().unwrap())
+        }))
+    }
+
+    #[tokio::test]
+    async fn pass_forwards_unchanged() {
+        let seen = Arc::new(Seen::default());
+        let interceptors: [Arc<dyn Interceptor>; 2] = [
+            Arc::new(Rule(|_: &PacketFrame| Verdict::Pass)),
+            seen.clone(),
+        ];
+
+        assert_eq!(
+            run(&interceptors, frame(1, &[1, 2])).await,
+            [(1, vec![1, 2])]
+        );
+        assert_eq!(*seen.0.lock().unwrap(), [(1, vec![1, 2])]);
+    }
+
+    #[tokio::test]
+    async fn drop_skips_later_interceptors() {
+        let seen = Arc::new(Seen::default());
+        let interceptors: [Arc<dyn Interceptor>; 2] = [
+            Arc::new(Rule(|_: &PacketFrame| Verdict::Drop)),
+            seen.clone(),
+        ];
+
+        assert_eq!(run(&interceptors, frame(1, &[1, 2])).await, []);
+        assert_eq!(*seen.0.lock().unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn rewrite_keeps_id_and_reaches_later_interceptors() {
+        let seen = Arc::new(Seen::default());
+        let interceptors: [Arc<dyn Interceptor>; 2] = [
+            Arc::new(Rule(|_: &PacketFrame| {
+                Verdict::Rewrite(BytesMut::from(&[7][..]))
+            })),
+            seen.clone(),
+        ];
+
+        assert_eq!(run(&interceptors, frame(1, &[1, 2])).await, [(1, vec![7])]);
+        assert_eq!(*seen.0.lock().unwrap(), [(1, vec![7])]);
+    }
+
+    #[tokio::test]
+    async fn later_interceptors_see_each_replacement() {
+        let interceptors: [Arc<dyn Interceptor>; 2] = [
+            Arc::new(Rule(|frame: &PacketFrame| {
+                Verdict::Replace(vec![
+                    self::frame(10, &[]),
+                    self::frame(frame.id, &frame.body),
+                ])
+            })),
+            Arc::new(Rule(|frame: &PacketFrame| match frame.id {
+                10 => Verdict::Rewrite(BytesMut::from(&[4, 2][..])),
+                _ => Verdict::Drop,
+            })),
+        ];
+
+        assert_eq!(run(&interceptors, frame(1, &[1])).await, [(10, vec![4, 2])]);
+    }
+
+    #[tokio::test]
+    async fn hold_waits_for_the_final_verdict() {
+        let (sender, receiver) = oneshot::channel();
+        let seen = Arc::new(Seen::default());
+        let interceptors: [Arc<dyn Interceptor>; 2] = [hold(receiver), seen.clone()];
+
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            sender.send(Verdict::Rewrite(BytesMut::from(&[5][..]))).ok();
+        });
+
+        assert_eq!(run(&interceptors, frame(1, &[1])).await, [(1, vec![5])]);
+        assert_eq!(*seen.0.lock().unwrap(), [(1, vec![5])]);
+    }
+
+    #[tokio::test]
+    async fn hold_with_dropped_sender_passes() {
+        let (sender, receiver) = oneshot::channel();
+        let seen = Arc::new(Seen::default());
+        let interceptors: [Arc<dyn Interceptor>; 2] = [hold(receiver), seen.clone()];
+        drop(sender);
+
+        assert_eq!(run(&interceptors, frame(1, &[1])).await, [(1, vec![1])]);
+        assert_eq!(*seen.0.lock().unwrap(), [(1, vec![1])]);
+    }
+
+    #[tokio::test]
+    async fn hold_resolving_to_hold_keeps_waiting() {
+        let (outer_sender, outer) = oneshot::channel();
+        let (inner_sender, inner) = oneshot::channel();
+        let seen = Arc::new(Seen::default());
+        let interceptors: [Arc<dyn Interceptor>; 2] = [hold(outer), seen.clone()];
+
+        outer_sender.send(Verdict::Hold(inner)).ok();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            inner_sender.send(Verdict::Drop).ok();
+        });
+
+        assert_eq!(run(&interceptors, frame(1, &[1])).await, []);
+        assert_eq!(*seen.0.lock().unwrap(), []);
+    }
+}
@@ -1,153 +1,402 @@
+mod capture;
+#[cfg(feature = "decode")]
+mod channels;
+mod connection_state;
+#[cfg(feature = "decode")]
+mod decode;
+#[cfg(feature = "serde")]
+mod definitions;
+mod display_filter;
+mod encryption;
+#[cfg(feature = "decode")]
+mod fields;
+mod hex;
+mod inject;
+mod interceptor;
 mod packet_io;
 mod packet_registry;
+mod replay;
+mod schema;
+mod session;
+mod shutdown;
 
-use std::{net::SocketAddr, sync::OnceLock};
+use std::net::SocketAddr;
 
-use tokio::net::TcpStream;
+use tokio::{net::TcpStream, sync::watch, task::JoinSet};
 
-use std::sync::{Arc, RwLock};
+use std::sync::Arc;
 
-use valence_core::protocol::{decode::PacketFrame, Decode, Packet as ValencePacket};
-use valence_network::packet::{
-    HandshakeC2s, HandshakeNextState, LoginCompressionS2c, LoginSuccessS2c,
-};
+use bytes::{BufMut, BytesMut};
+use valence_core::protocol::{decode::PacketFrame, Decode, Encode, Packet as ValencePacket};
+use valence_network::packet::{LoginHelloS2c, LoginKeyC2s};
 
-use crate::{packet_io::PacketIo, packet_registry::PacketRegistry};
+use crate::{
+    encryption::{ProxyKeys, SessionCrypto},
+    inject::InjectQueue,
+    interceptor::run_interceptors,
+    packet_io::PacketIo,
+    packet_registry::PacketRegistry,
+    shutdown::{wait_for_force, wait_for_shutdown},
+};
 
-pub use packet_registry::Packet;
+pub use capture::{
+    replay_timed, CaptureHeader, CaptureReader, CaptureWriter, CAPTURE_FORMAT_VERSION,
+};
+#[cfg(feature = "decode")]
+pub use channels::{register_channel_decoder, ChannelDecoder};
+#[cfg(feature = "decode")]
+pub use decode::{
+    decode_fields, decode_packet, packet_layout, packet_to_string, DecodeError, Decoded,
+    FieldLayout,
+};
+#[cfg(feature = "serde")]
+pub use definitions::PacketDefinitions;
+pub use display_filter::DisplayFilter;
+#[cfg(feature = "decode")]
+pub use fields::Field;
+pub use hex::parse_hex;
+pub use inject::Injector;
+pub use interceptor::{InterceptContext, Interceptor, Verdict};
+pub use packet_registry::ProxyEvent;
+pub use packet_registry::{packet_table, Packet, PacketTable};
+pub use replay::{replay_session, Divergence, ReplayConfig, ReplayReport};
+pub use schema::{PacketSchema, SchemaField, SchemaType};
+pub use session::{CloseReason, Session, SessionId};
+pub use shutdown::{RunSummary, ShutdownHandle, ShutdownMode};
 
 pub use crate::packet_registry::PacketSide;
 pub use crate::packet_registry::PacketState;
 
-static PACKET_REGISTRY: OnceLock<Arc<PacketRegistry>> = OnceLock::new();
-
 include!(concat!(env!("OUT_DIR"), "/packets.rs"));
 
+// the decoders and the state tracking in `Proxy::process` use valence's packets
+const _: () = assert!(STD_PROTOCOL_VERSION == valence_core::PROTOCOL_VERSION);
+
 pub struct Proxy {
     listener_addr: SocketAddr,
     server_addr: SocketAddr,
+    registry: Arc<PacketRegistry>,
+    shutdown: Arc<watch::Sender<Option<ShutdownMode>>>,
+    keys: Option<Arc<ProxyKeys>>,
+    interceptors: Vec<Arc<dyn Interceptor>>,
+    injector: Injector,
 }
 
 impl Proxy {
     pub fn new(listener_addr: SocketAddr, server_addr: SocketAddr) -> Self {
-        PACKET_REGISTRY.get_or_init(|| {
-            let registry = PacketRegistry::new();
-            registry.register_all(&STD_PACKETS);
-            Arc::new(registry)
-        });
+        let registry = PacketRegistry::new();
+        for table in PACKET_TABLES {
+            registry.register_all(table.protocol_version, table.packets);
+        }
+
+        let (shutdown, _) = watch::channel(None);
 
         Proxy {
             listener_addr,
             server_addr,
+            registry: Arc::new(registry),
+            shutdown: Arc::new(shutdown),
+            keys: None,
+            interceptors: Vec::new(),
+            injector: Injector::default(),
         }
     }
 
-    pub fn subscribe(&self) -> flume::Receiver<Packet> {
-        PACKET_REGISTRY.get().unwrap().subscribe()
+    /// Terminates encryption at the proxy so online-mode logins can be inspected.
+    ///
+    /// Clients are handed the proxy's own key instead of the server's, so this only works
+    /// against servers that don't verify the session with Mojang.
+    pub fn with_encryption(mut self) -> anyhow::Result<Self> {
+        self.keys = Some(Arc::new(ProxyKeys::generate()?));
+        Ok(self)
     }
 
-    pub async fn run(&self) -> anyhow::Result<()> {
+    /// Adds an interceptor to the end of the chain every relayed packet passes through.
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn Interceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Names packets from definitions loaded at runtime, over the bundled names.
+    #[cfg(feature = "serde")]
+    pub fn with_packet_definitions(self, definitions: &PacketDefinitions) -> Self {
+        self.registry
+            .define(definitions.protocol_version, &definitions.packets);
+        self
+    }
+
+    pub fn injector(&self) -> Injector {
+        self.injector.clone()
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(self.shutdown.clone())
+    }
+
+    /// Every subscriber of the same proxy shares one queue, so each event is delivered to only
+    /// one of them.
+    pub fn subscribe(&self) -> flume::Receiver<ProxyEvent> {
+        self.registry.subscribe()
+    }
+
+    /// Accepts connections until a shutdown is requested through a [`ShutdownHandle`], then
+    /// waits for the live sessions according to the requested [`ShutdownMode`].
+    pub async fn run(&self) -> anyhow::Result<RunSummary> {
         let listener = tokio::net::TcpListener::bind(self.listener_addr).await?;
+        let mut shutdown = self.shutdown.subscribe();
+        let mut sessions = JoinSet::new();
+        let mut summary = RunSummary::default();
+        let interceptors: Arc<[Arc<dyn Interceptor>]> = self.interceptors.clone().into();
+
+        loop {
+            tokio::select! {
+                res = listener.accept() => {
+                    let (client, client_addr) = match res {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            tracing::error!("Failed to accept connection: {}", e);
+                            break;
+                        }
+                    };
 
-        while let Ok((client, _addr)) = listener.accept().await {
-            let server_addr = self.server_addr;
-            tokio::spawn(async move {
-                let server = TcpStream::connect(server_addr).await?;
+                    let server_addr = self.server_addr;
+                    let registry = self.registry.clone();
+                    let shutdown = self.shutdown.subscribe();
+                    let keys = self.keys.clone();
+                    let interceptors = interceptors.clone();
+                    let injector = self.injector.clone();
+                    sessions.spawn(async move {
+                        let session = registry.open_session(client_addr, server_addr);
+                        let inject = injector.register(session.id);
+
+                        let reason = match TcpStream::connect(server_addr).await {
+                            Ok(server) => match Self::process(
+                                registry.clone(),
+                                session.clone(),
+                                client,
+                                server,
+                                keys,
+                                interceptors,
+                                inject,
+                                shutdown,
+                            )
+                            .await
+                            {
+                                Ok(reason) => reason,
+                                Err(e) => CloseReason::Error(e.to_string()),
+                            },
+                            Err(e) => CloseReason::ServerUnreachable(e.to_string()),
+                        };
 
-                if let Err(e) = Self::process(client, server).await {
-                    if !e.to_string().contains("unexpected end of file") {
-                        // bit meh to do it like this but it works
-                        tracing::error!("Error: {:?}", e);
-                    }
+                        if let CloseReason::Error(e) = &reason {
+                            tracing::error!("Error: {}", e);
+                        }
+
+                        injector.unregister(session.id);
+                        registry.close_session(&session, reason.clone());
+                        reason
+                    });
+
+                    summary.sessions_accepted += 1;
                 }
+                _ = wait_for_shutdown(&mut shutdown) => break,
+                // reap finished sessions so the set doesn't grow for the lifetime of the proxy
+                Some(_) = sessions.join_next(), if !sessions.is_empty() => {}
+            }
+        }
+
+        // free the port before waiting on the sessions
+        drop(listener);
 
-                Ok::<(), anyhow::Error>(())
-            });
+        while let Some(res) = sessions.join_next().await {
+            match res {
+                Ok(CloseReason::Shutdown) => summary.sessions_closed += 1,
+                Ok(_) => summary.sessions_drained += 1,
+                Err(e) => tracing::error!("Session task failed: {}", e),
+            }
         }
-        Ok(())
+
+        Ok(summary)
     }
 
-    async fn process(client: TcpStream, server: TcpStream) -> anyhow::Result<()> {
+    #[allow(clippy::too_many_arguments)]
+    async fn process(
+        registry: Arc<PacketRegistry>,
+        session: Arc<Session>,
+        client: TcpStream,
+        server: TcpStream,
+        keys: Option<Arc<ProxyKeys>>,
+        interceptors: Arc<[Arc<dyn Interceptor>]>,
+        inject: InjectQueue,
+        mut shutdown: watch::Receiver<Option<ShutdownMode>>,
+    ) -> anyhow::Result<CloseReason> {
         let client = PacketIo::new(client);
         let server = PacketIo::new(server);
 
         let (mut client_reader, mut client_writer) = client.split();
         let (mut server_reader, mut server_writer) = server.split();
 
-        let current_state_inner = Arc::new(RwLock::new(PacketState::Handshaking));
-        let threshold_inner = Arc::new(RwLock::new(None));
-
-        let current_state = current_state_inner.clone();
-        let threshold = threshold_inner.clone();
-        let c2s = tokio::spawn(async move {
+        let connection_inner = session.connection.clone();
+        let crypto_inner = Arc::new(SessionCrypto::new());
+        let InjectQueue {
+            to_server,
+            to_client,
+        } = inject;
+
+        let connection = connection_inner.clone();
+        let crypto = crypto_inner.clone();
+        let session_inner = session.clone();
+        let registry_inner = registry.clone();
+        let keys_inner = keys.clone();
+        let interceptors_inner = interceptors.clone();
+        let mut c2s = tokio::spawn(async move {
+            let session = session_inner;
+            let registry = registry_inner;
+            let keys = keys_inner;
+            let interceptors = interceptors_inner;
             loop {
-                client_reader.set_compression(*threshold.read().unwrap());
-                server_writer.set_compression(*threshold.read().unwrap());
+                let write_threshold = connection.threshold();
+                client_reader.set_compression(write_threshold);
+                server_writer.set_compression(write_threshold);
                 // client to server handling
-                let packet = client_reader.recv_packet_raw().await?;
-
-                let state = {
-                    let state = current_state.read().unwrap();
-                    *state
+                let packet = tokio::select! {
+                    packet = client_reader.recv_packet_raw() => packet?,
+                    Ok(frame) = to_server.recv_async() => {
+                        let state = connection.state();
+                        registry.process(
+                            &session,
+                            PacketSide::Serverbound,
+                            state,
+                            write_threshold,
+                            &frame,
+                        )?;
+                        server_writer.send_packet_raw(&frame).await?;
+                        continue;
+                    }
                 };
 
-                PACKET_REGISTRY.get().unwrap().process(
+                let state = connection.state();
+
+                registry.process(
+                    &session,
                     crate::packet_registry::PacketSide::Serverbound,
                     state,
-                    *threshold.read().unwrap(),
+                    connection.threshold(),
                     &packet,
                 )?;
 
-                if state == PacketState::Handshaking {
-                    if let Some(handshake) = extrapolate_packet::<HandshakeC2s>(&packet) {
-                        *current_state.write().unwrap() = match handshake.next_state {
-                            HandshakeNextState::Status => PacketState::Status,
-                            HandshakeNextState::Login => PacketState::Login,
-                        };
+                if let Some(handshake) = connection.track_serverbound(state, &packet) {
+                    let version = handshake.protocol_version.0;
+                    let _ = session.protocol_version.set(version);
+                    if !registry.has_table(version) {
+                        tracing::warn!(
+                            "Session {} uses protocol {}, which has no packet table, naming \
+                             packets as protocol {}",
+                            session.id,
+                            version,
+                            STD_PROTOCOL_VERSION
+                        );
+                    }
+                }
+
+                if let (PacketState::Login, Some(keys)) = (state, &keys) {
+                    if let Some(key) = extrapolate_packet::<LoginKeyC2s>(&packet) {
+                        let (frame, client_secret, server_secret) =
+                            encryption::intercept_client_key(keys, &crypto, &key)?;
+
+                        client_reader.enable_encryption(&client_secret);
+                        server_writer.send_packet_raw(&frame).await?;
+                        server_writer.enable_encryption(&server_secret);
+                        continue;
                     }
                 }
 
-                server_writer.send_packet_raw(&packet).await?;
+                let ctx = InterceptContext {
+                    session: &session,
+                    side: PacketSide::Serverbound,
+                    state,
+                    name: registry.packet_name(&session, PacketSide::Serverbound, state, packet.id),
+                    threshold: write_threshold,
+                };
+                for frame in run_interceptors(&interceptors, &ctx, packet).await {
+                    server_writer.send_packet_raw(&frame).await?;
+                }
             }
 
             #[allow(unreachable_code)]
             Ok::<(), anyhow::Error>(())
         });
 
-        let current_state = current_state_inner.clone();
-        let threshold = threshold_inner.clone();
-        let s2c = tokio::spawn(async move {
+        let connection = connection_inner.clone();
+        let crypto = crypto_inner.clone();
+        let mut s2c = tokio::spawn(async move {
             loop {
-                server_reader.set_compression(*threshold.read().unwrap());
-                client_writer.set_compression(*threshold.read().unwrap());
+                let write_threshold = connection.threshold();
+                server_reader.set_compression(write_threshold);
+                client_writer.set_compression(write_threshold);
                 // server to client handling
-                let packet = server_reader.recv_packet_raw().await?;
+                let packet = tokio::select! {
+                    packet = server_reader.recv_packet_raw() => packet?,
+                    Ok(frame) = to_client.recv_async() => {
+                        let state = connection.state();
+                        registry.process(
+                            &session,
+                            PacketSide::Clientbound,
+                            state,
+                            write_threshold,
+                            &frame,
+                        )?;
+
+                        let client_secret = crypto.lock().unwrap().take_pending_client_secret();
+                        if let Some(client_secret) = client_secret {
+                            client_writer.enable_encryption(&client_secret);
+                        }
 
-                let state = {
-                    let state = current_state.read().unwrap();
-                    *state
+                        client_writer.send_packet_raw(&frame).await?;
+                        continue;
+                    }
                 };
 
-                if state == PacketState::Login {
-                    if let Some(compression) = extrapolate_packet::<LoginCompressionS2c>(&packet) {
-                        if compression.threshold.0 >= 0 {
-                            *threshold.write().unwrap() = Some(compression.threshold.0 as u32);
-                        }
-                    };
+                let state = connection.state();
 
-                    if extrapolate_packet::<LoginSuccessS2c>(&packet).is_some() {
-                        *current_state.write().unwrap() = PacketState::Play;
-                    };
-                }
+                connection.track_clientbound(state, &packet);
 
-                PACKET_REGISTRY.get().unwrap().process(
+                registry.process(
+                    &session,
                     crate::packet_registry::PacketSide::Clientbound,
                     state,
-                    *threshold.read().unwrap(),
+                    connection.threshold(),
                     &packet,
                 )?;
 
-                client_writer.send_packet_raw(&packet).await?;
+                if let (PacketState::Login, Some(keys)) = (state, &keys) {
+                    if let Some(hello) = extrapolate_packet::<LoginHelloS2c>(&packet) {
+                        let (frame, server_secret) =
+                            encryption::intercept_server_hello(keys, &crypto, &hello)?;
+
+                        // the server encrypts everything after it receives our response
+                        server_reader.enable_encryption(&server_secret);
+                        client_writer.send_packet_raw(&frame).await?;
+                        continue;
+                    }
+                }
+
+                // the client switched to encryption right after sending its response
+                let client_secret = crypto.lock().unwrap().take_pending_client_secret();
+                if let Some(client_secret) = client_secret {
+                    client_writer.enable_encryption(&client_secret);
+                }
+
+                let ctx = InterceptContext {
+                    session: &session,
+                    side: PacketSide::Clientbound,
+                    state,
+                    name: registry.packet_name(&session, PacketSide::Clientbound, state, packet.id),
+                    threshold: write_threshold,
+                };
+                for frame in run_interceptors(&interceptors, &ctx, packet).await {
+                    client_writer.send_packet_raw(&frame).await?;
+                }
             }
 
             #[allow(unreachable_code)]
@@ -155,13 +404,41 @@ impl Proxy {
         });
 
         // wait for either to finish
-        tokio::select! {
-            res = c2s => res?,
-            res = s2c => res?,
-        }
+        let reason = tokio::select! {
+            res = &mut c2s => res.map(|res| close_reason(res, CloseReason::ClientDisconnected)),
+            res = &mut s2c => res.map(|res| close_reason(res, CloseReason::ServerDisconnected)),
+            _ = wait_for_force(&mut shutdown) => Ok(CloseReason::Shutdown),
+        };
+
+        // take down the other direction too, dropping both sockets
+        c2s.abort();
+        s2c.abort();
+
+        Ok(reason?)
     }
 }
 
+/// A direction ending on EOF is a regular disconnect, anything else is reported as-is.
+fn close_reason(res: anyhow::Result<()>, on_eof: CloseReason) -> CloseReason {
+    match res {
+        Ok(()) => on_eof,
+        Err(e) => match e.downcast_ref::<std::io::Error>() {
+            Some(io) if io.kind() == std::io::ErrorKind::UnexpectedEof => on_eof,
+            _ => CloseReason::Error(e.to_string()),
+        },
+    }
+}
+
+pub(crate) fn encode_frame<P>(packet: &P) -> anyhow::Result<PacketFrame>
+where
+    P: ValencePacket + Encode,
+{
+    let mut body = BytesMut::new();
+    packet.encode((&mut body).writer())?;
+
+    Ok(PacketFrame { id: P::ID, body })
+}
+
 fn extrapolate_packet<'a, P>(packet: &'a PacketFrame) -> Option<P>
 where
     P: ValencePacket + Decode<'a> + Clone,
@@ -45,6 +45,11 @@ impl PacketIoReader {
         self.threshold = threshold;
         self.dec.set_compression(threshold);
     }
+
+    /// Decrypts everything read from here on with AES/CFB8.
+    pub(crate) fn enable_encryption(&mut self, key: &[u8; 16]) {
+        self.dec.enable_encryption(key);
+    }
 }
 
 pub(crate) struct PacketIoWriter {
@@ -140,6 +145,11 @@ impl PacketIoWriter {
         self.threshold = threshold;
         self.enc.set_compression(threshold);
     }
+
+    /// Encrypts everything written from here on with AES/CFB8.
+    pub(crate) fn enable_encryption(&mut self, key: &[u8; 16]) {
+        self.enc.enable_encryption(key);
+    }
 }
 
 pub(crate) struct PacketIo {
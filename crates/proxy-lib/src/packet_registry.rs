@@ -1,89 +1,256 @@
 use std::{
+    collections::HashMap,
     hash::{Hash, Hasher},
-    sync::RwLock,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, OnceLock, RwLock,
+    },
 };
 
 use bytes::Bytes;
 use time::OffsetDateTime;
 use valence_core::protocol::decode::PacketFrame;
 
+use crate::{
+    connection_state::ConnectionState,
+    schema::PacketSchema,
+    session::{CloseReason, Session, SessionId},
+    PACKET_TABLES, STD_PACKETS, STD_PROTOCOL_VERSION,
+};
+
+/// Packet names and ids of one Minecraft version.
+#[derive(Debug)]
+pub struct PacketTable {
+    pub protocol_version: i32,
+    /// Minecraft version, e.g. "1.20.1"
+    pub version: &'static str,
+    pub packets: &'static [Packet],
+}
+
+/// The bundled table for a protocol version.
+pub fn packet_table(protocol_version: i32) -> Option<&'static PacketTable> {
+    PACKET_TABLES
+        .iter()
+        .find(|table| table.protocol_version == protocol_version)
+}
+
+/// Adds `packets` to `table`, replacing the ones with the same side, state and id.
+pub(crate) fn overlay_packets(table: &mut Vec<Packet>, packets: &[Packet]) {
+    for packet in packets {
+        match table
+            .iter_mut()
+            .find(|p| p.side == packet.side && p.state == packet.state && p.id == packet.id)
+        {
+            Some(existing) => *existing = packet.clone(),
+            None => table.push(packet.clone()),
+        }
+    }
+}
+
+/// The bundled packets of a protocol version, [`STD_PACKETS`] if it is unknown or has no table.
+pub(crate) fn bundled_packets(protocol_version: Option<i32>) -> &'static [Packet] {
+    match protocol_version.and_then(packet_table) {
+        Some(table) => table.packets,
+        None => &STD_PACKETS,
+    }
+}
+
 pub struct PacketRegistry {
-    packets: RwLock<Vec<Packet>>,
-    receiver: flume::Receiver<Packet>,
-    sender: flume::Sender<Packet>,
+    /// Packet tables by protocol version
+    tables: RwLock<HashMap<i32, Vec<Packet>>>,
+    next_session_id: AtomicU64,
+    receiver: flume::Receiver<ProxyEvent>,
+    sender: flume::Sender<ProxyEvent>,
 }
 
 #[allow(unused)]
 impl PacketRegistry {
     pub fn new() -> Self {
-        let (sender, receiver) = flume::unbounded::<Packet>();
+        let (sender, receiver) = flume::unbounded::<ProxyEvent>();
 
         Self {
-            packets: RwLock::new(Vec::new()),
+            tables: RwLock::new(HashMap::new()),
+            next_session_id: AtomicU64::new(1),
             receiver,
             sender,
         }
     }
 
-    pub fn subscribe(&self) -> flume::Receiver<Packet> {
+    pub fn subscribe(&self) -> flume::Receiver<ProxyEvent> {
         self.receiver.clone()
     }
 
-    pub fn register(&self, packet: Packet) {
-        self.packets.write().unwrap().push(packet);
+    pub fn register(&self, protocol_version: i32, packet: Packet) {
+        self.tables
+            .write()
+            .unwrap()
+            .entry(protocol_version)
+            .or_default()
+            .push(packet);
     }
 
     // register_all(takes an array of packets)
-    pub fn register_all(&self, packets: &[Packet]) {
-        self.packets.write().unwrap().extend_from_slice(packets);
+    pub fn register_all(&self, protocol_version: i32, packets: &[Packet]) {
+        self.tables
+            .write()
+            .unwrap()
+            .entry(protocol_version)
+            .or_default()
+            .extend_from_slice(packets);
     }
 
-    fn get_specific_packet(&self, side: PacketSide, state: PacketState, packet_id: i32) -> Packet {
-        let time = match OffsetDateTime::now_local() {
-            Ok(time) => time,
-            Err(_) => OffsetDateTime::now_utc(),
-        };
+    /// Adds packets to the table of a protocol version, replacing the ones with the same side,
+    /// state and id. A version without a table gets a copy of the [`STD_PROTOCOL_VERSION`] one
+    /// to add to, the table its sessions were read with until now.
+    pub fn define(&self, protocol_version: i32, packets: &[Packet]) {
+        let mut tables = self.tables.write().unwrap();
+        if !tables.contains_key(&protocol_version) {
+            let fallback = tables.get(&STD_PROTOCOL_VERSION).cloned();
+            tables.insert(protocol_version, fallback.unwrap_or_default());
+        }
+        overlay_packets(tables.get_mut(&protocol_version).unwrap(), packets);
+    }
 
-        self.packets
-            .read()
-            .unwrap()
-            .iter()
-            .find(|packet| packet.id == packet_id && packet.side == side && packet.state == state)
-            .unwrap_or(&Packet {
-                side,
-                state,
-                id: packet_id,
-                timestamp: Some(time),
-                name: "Unknown Packet",
-                data: None,
-            })
-            .clone()
+    pub fn has_table(&self, protocol_version: i32) -> bool {
+        self.tables.read().unwrap().contains_key(&protocol_version)
+    }
+
+    /// Runs `f` on the table of the session's protocol version, falling back to the one of
+    /// [`STD_PROTOCOL_VERSION`] before the handshake or for versions without a table.
+    fn with_table<T>(&self, session: &Session, f: impl FnOnce(&[Packet]) -> T) -> T {
+        let tables = self.tables.read().unwrap();
+        let table = session
+            .protocol_version()
+            .and_then(|version| tables.get(&version))
+            .or_else(|| tables.get(&STD_PROTOCOL_VERSION));
+
+        f(table.map(Vec::as_slice).unwrap_or_default())
+    }
+
+    fn get_specific_packet(
+        &self,
+        session: &Session,
+        side: PacketSide,
+        state: PacketState,
+        packet_id: i32,
+    ) -> Packet {
+        let time = now();
+
+        self.with_table(session, |packets| {
+            packets
+                .iter()
+                .find(|packet| {
+                    packet.id == packet_id && packet.side == side && packet.state == state
+                })
+                .unwrap_or(&Packet {
+                    side,
+                    state,
+                    id: packet_id,
+                    timestamp: Some(time),
+                    name: "Unknown Packet",
+                    data: None,
+                    session: None,
+                    schema: None,
+                })
+                .clone()
+        })
+    }
+
+    /// Name of a packet in the session's protocol version.
+    pub fn packet_name(
+        &self,
+        session: &Session,
+        side: PacketSide,
+        state: PacketState,
+        packet_id: i32,
+    ) -> &'static str {
+        self.with_table(session, |packets| {
+            find_packet_name(packets, side, state, packet_id)
+        })
+    }
+
+    pub fn open_session(&self, client_addr: SocketAddr, server_addr: SocketAddr) -> Arc<Session> {
+        let accepted_at = now();
+
+        let session = Arc::new(Session {
+            id: SessionId(self.next_session_id.fetch_add(1, Ordering::Relaxed)),
+            client_addr,
+            server_addr,
+            accepted_at,
+            protocol_version: OnceLock::new(),
+            connection: Arc::new(ConnectionState::new()),
+        });
+
+        // nobody listening is not an error
+        let _ = self.sender.send(ProxyEvent::SessionOpened(session.clone()));
+
+        session
+    }
+
+    pub fn close_session(&self, session: &Arc<Session>, reason: CloseReason) {
+        let _ = self
+            .sender
+            .send(ProxyEvent::SessionClosed(session.clone(), reason));
     }
 
     pub fn process(
         &self,
+        session: &Arc<Session>,
         side: PacketSide,
         state: PacketState,
         threshold: Option<u32>,
         packet: &PacketFrame,
     ) -> anyhow::Result<()> {
-        let mut p = self.get_specific_packet(side, state, packet.id);
-        let time = match OffsetDateTime::now_local() {
-            Ok(time) => time,
-            Err(_) => OffsetDateTime::now_utc(),
-        };
+        let mut p = self.get_specific_packet(session, side, state, packet.id);
+        let time = now();
 
         p.data = Some(packet.body.clone().freeze());
         p.timestamp = Some(time);
+        p.session = Some(session.clone());
 
         // store in received_packets
-        self.sender.send(p)?;
+        self.sender.send(ProxyEvent::Packet(p))?;
 
         Ok(())
     }
 }
 
-#[derive(Clone, Debug, Eq)]
+/// The local time, or UTC if the local offset can't be determined.
+pub(crate) fn now() -> OffsetDateTime {
+    match OffsetDateTime::now_local() {
+        Ok(time) => time,
+        Err(_) => OffsetDateTime::now_utc(),
+    }
+}
+
+/// Name of the packet with the given identity, or "Unknown Packet" if it isn't in `packets`.
+pub(crate) fn find_packet_name(
+    packets: &[Packet],
+    side: PacketSide,
+    state: PacketState,
+    packet_id: i32,
+) -> &'static str {
+    packets
+        .iter()
+        .find(|packet| packet.id == packet_id && packet.side == side && packet.state == state)
+        .map(|packet| packet.name)
+        .unwrap_or("Unknown Packet")
+}
+
+pub(crate) fn find_packet_id(
+    packets: &[Packet],
+    side: PacketSide,
+    state: PacketState,
+    name: &str,
+) -> Option<i32> {
+    packets
+        .iter()
+        .find(|packet| packet.name == name && packet.side == side && packet.state == state)
+        .map(|packet| packet.id)
+}
+
+#[derive(Clone, Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Packet {
     pub side: PacketSide,
@@ -96,6 +263,20 @@ pub struct Packet {
     /// Uncompressed packet data
     #[cfg_attr(feature = "serde", serde[skip])]
     pub data: Option<Bytes>,
+    /// The connection this packet was captured on
+    #[cfg_attr(feature = "serde", serde[skip])]
+    pub session: Option<Arc<Session>>,
+    /// Field layout from a definitions file, used to decode packets valence doesn't know
+    #[cfg_attr(feature = "serde", serde[skip])]
+    pub schema: Option<Arc<PacketSchema>>,
+}
+
+/// Everything a subscriber receives from the proxy, in the order it happened.
+#[derive(Clone, Debug)]
+pub enum ProxyEvent {
+    SessionOpened(Arc<Session>),
+    Packet(Packet),
+    SessionClosed(Arc<Session>, CloseReason),
 }
 
 impl PartialEq for Packet {
@@ -107,6 +288,9 @@ impl PartialEq for Packet {
     }
 }
 
+// the session isn't compared, so it doesn't need to be `Eq`
+impl Eq for Packet {}
+
 impl Hash for Packet {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.id.hash(state);
@@ -130,3 +314,93 @@ pub enum PacketSide {
     Clientbound,
     Serverbound,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> PacketRegistry {
+        let registry = PacketRegistry::new();
+        for table in PACKET_TABLES {
+            registry.register_all(table.protocol_version, table.packets);
+        }
+        registry
+    }
+
+    fn play_s2c(id: i32, name: &'static str) -> Packet {
+        Packet {
+            side: PacketSide::Clientbound,
+            state: PacketState::Play,
+            id,
+            timestamp: None,
+            name,
+            data: None,
+            session: None,
+            schema: None,
+        }
+    }
+
+    #[test]
+    fn sessions_use_the_table_of_their_version() {
+        let registry = registry();
+        let addr = "127.0.0.1:25565".parse().unwrap();
+
+        assert_eq!(packet_table(762).unwrap().version, "1.19.4");
+        assert!(registry.has_table(762));
+
+        // only in the 1.20.1 table, so a 1.19.4 session must not see it
+        registry.register(763, play_s2c(0x7f, "Only1201S2c"));
+
+        let old = registry.open_session(addr, addr);
+        old.protocol_version.set(762).unwrap();
+        let new = registry.open_session(addr, addr);
+        new.protocol_version.set(763).unwrap();
+
+        let name = |session: &Session, id| {
+            registry.packet_name(session, PacketSide::Clientbound, PacketState::Play, id)
+        };
+        assert_eq!(name(&old, 0x0d), "ChunkBiomeDataS2c");
+        assert_eq!(name(&old, 0x7f), "Unknown Packet");
+        assert_eq!(name(&new, 0x7f), "Only1201S2c");
+    }
+
+    #[test]
+    fn unknown_versions_fall_back_to_the_std_table() {
+        let registry = registry();
+        let addr = "127.0.0.1:25565".parse().unwrap();
+
+        let session = registry.open_session(addr, addr);
+        session.protocol_version.set(4).unwrap();
+
+        assert!(!registry.has_table(4));
+        assert_eq!(
+            registry.packet_name(
+                &session,
+                PacketSide::Serverbound,
+                PacketState::Handshaking,
+                0
+            ),
+            "HandshakeC2s"
+        );
+    }
+
+    #[test]
+    fn definitions_for_versions_without_a_table_overlay_the_std_one() {
+        let registry = registry();
+        let addr = "127.0.0.1:25565".parse().unwrap();
+
+        registry.define(
+            4,
+            &[play_s2c(0x7f, "ModdedS2c"), play_s2c(0x0d, "RenamedS2c")],
+        );
+
+        let session = registry.open_session(addr, addr);
+        session.protocol_version.set(4).unwrap();
+
+        let name =
+            |id| registry.packet_name(&session, PacketSide::Clientbound, PacketState::Play, id);
+        assert_eq!(name(0x7f), "ModdedS2c");
+        assert_eq!(name(0x0d), "RenamedS2c");
+        assert_eq!(name(0x01), "EntitySpawnS2c");
+    }
+}
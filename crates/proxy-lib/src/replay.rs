@@ -0,0 +1,530 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use bytes::BytesMut;
+use tokio::{net::TcpStream, sync::watch};
+use valence_core::protocol::decode::PacketFrame;
+use valence_network::packet::LoginHelloS2c;
+
+use crate::{
+    connection_state::ConnectionState,
+    extrapolate_packet,
+    packet_io::PacketIo,
+    packet_registry::{
+        bundled_packets, find_packet_id, find_packet_name, now, Packet, PacketSide, PacketState,
+    },
+};
+
+pub struct ReplayConfig {
+    pub server_addr: SocketAddr,
+    /// Clientbound packets the recorded client waited for. Before sending a serverbound packet
+    /// the driver waits until the server sent each of these as often as it did up to that point
+    /// in the recording.
+    pub triggers: Vec<String>,
+    /// How long to wait for the server to catch up with a trigger
+    pub trigger_timeout: Duration,
+    /// Answer the server's keepalives and skip the recorded ones, which carry stale ids
+    pub answer_keepalives: bool,
+    /// How long to keep listening after the last packet was sent
+    pub settle_time: Duration,
+    /// Clientbound packets left out of the comparison because they depend on timing
+    pub ignored: Vec<String>,
+}
+
+impl ReplayConfig {
+    pub fn new(server_addr: SocketAddr) -> Self {
+        Self {
+            server_addr,
+            triggers: ["LoginCompressionS2c", "LoginSuccessS2c", "GameJoinS2c"]
+                .map(String::from)
+                .to_vec(),
+            trigger_timeout: Duration::from_secs(10),
+            answer_keepalives: true,
+            settle_time: Duration::from_secs(2),
+            ignored: vec!["KeepAliveS2c".to_string()],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Divergence {
+    /// First position where the recorded and actual clientbound streams stop matching
+    FirstMismatch {
+        index: usize,
+        expected: Option<&'static str>,
+        actual: Option<&'static str>,
+    },
+    /// A clientbound packet was received a different number of times than recorded
+    CountMismatch {
+        state: PacketState,
+        name: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct ReplayReport {
+    /// Serverbound packets sent to the server
+    pub sent: usize,
+    /// Every clientbound packet the server sent back
+    pub received: Vec<Packet>,
+    pub divergences: Vec<Divergence>,
+    /// Why the replay stopped early, if it did
+    pub error: Option<String>,
+}
+
+/// Plays the serverbound packets of a recorded session against `config.server_addr` and
+/// compares what the server answers with what was recorded.
+///
+/// `recorded` holds the packets of a single session in capture order. Only offline-mode
+/// servers are supported, as the recorded encryption handshake can't be repeated.
+pub async fn replay_session(
+    recorded: &[Packet],
+    config: &ReplayConfig,
+) -> anyhow::Result<ReplayReport> {
+    let server = TcpStream::connect(config.server_addr)
+        .await
+        .context("failed to connect to server")?;
+    let (mut reader, writer) = PacketIo::new(server).split();
+    let writer = Arc::new(tokio::sync::Mutex::new(writer));
+
+    let connection = Arc::new(ConnectionState::new());
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let (trigger_sender, mut triggers_seen) = watch::channel(HashMap::new());
+
+    // the recorded handshake goes out as-is, so the server answers in the recorded version
+    let packets = bundled_packets(
+        recorded
+            .iter()
+            .find_map(|p| p.session.as_ref()?.protocol_version()),
+    );
+
+    let keepalive_c2s = find_packet_id(
+        packets,
+        PacketSide::Serverbound,
+        PacketState::Play,
+        "KeepAliveC2s",
+    );
+
+    let reader_task = {
+        let writer = writer.clone();
+        let connection = connection.clone();
+        let received = received.clone();
+        let triggers = config.triggers.clone();
+        let answer_keepalives = config.answer_keepalives;
+
+        tokio::spawn(async move {
+            let mut threshold = None;
+            loop {
+                let frame = reader.recv_packet_raw().await?;
+                let state = connection.state();
+
+                if state == PacketState::Login
+                    && extrapolate_packet::<LoginHelloS2c>(&frame).is_some()
+                {
+                    bail!("server requested encryption, only offline-mode is supported");
+                }
+
+                connection.track_clientbound(state, &frame);
+                if connection.threshold() != threshold {
+                    threshold = connection.threshold();
+                    reader.set_compression(threshold);
+                    writer.lock().await.set_compression(threshold);
+                }
+
+                let name = find_packet_name(packets, PacketSide::Clientbound, state, frame.id);
+
+                if answer_keepalives && state == PacketState::Play && name == "KeepAliveS2c" {
+                    if let Some(id) = keepalive_c2s {
+                        // the keepalive id is echoed back as-is
+                        let reply = PacketFrame {
+                            id,
+                            body: frame.body.clone(),
+                        };
+                        writer.lock().await.send_packet_raw(&reply).await?;
+                    }
+                }
+
+                if triggers.iter().any(|trigger| trigger == name) {
+                    trigger_sender.send_modify(|seen| *seen.entry(name).or_insert(0) += 1);
+                }
+
+                received.lock().unwrap().push(Packet {
+                    side: PacketSide::Clientbound,
+                    state,
+                    id: frame.id,
+                    timestamp: Some(now()),
+                    name,
+                    data: Some(frame.body.freeze()),
+                    session: None,
+                    schema: None,
+                });
+            }
+
+            #[allow(unreachable_code)]
+            Ok::<(), anyhow::Error>(())
+        })
+    };
+
+    let mut sent = 0;
+    let mut error = None;
+    let mut triggers_required = HashMap::new();
+
+    for packet in recorded {
+        if packet.side == PacketSide::Clientbound {
+            if config.triggers.iter().any(|trigger| trigger == packet.name) {
+                *triggers_required.entry(packet.name).or_insert(0) += 1;
+            }
+            continue;
+        }
+
+        if config.answer_keepalives
+            && packet.state == PacketState::Play
+            && packet.name == "KeepAliveC2s"
+        {
+            continue;
+        }
+
+        if let Err(e) = wait_for_triggers(
+            &mut triggers_seen,
+            &triggers_required,
+            config.trigger_timeout,
+        )
+        .await
+        {
+            error = Some(e.to_string());
+            break;
+        }
+
+        let frame = PacketFrame {
+            id: packet.id,
+            body: BytesMut::from(packet.data.as_deref().unwrap_or_default()),
+        };
+
+        if let Err(e) = writer.lock().await.send_packet_raw(&frame).await {
+            error = Some(e.to_string());
+            break;
+        }
+        sent += 1;
+
+        connection.track_serverbound(connection.state(), &frame);
+    }
+
+    if error.is_none() {
+        tokio::time::sleep(config.settle_time).await;
+    }
+
+    if reader_task.is_finished() {
+        if let Ok(Err(e)) = reader_task.await {
+            error.get_or_insert_with(|| e.to_string());
+        }
+    } else {
+        reader_task.abort();
+    }
+
+    let received = std::mem::take(&mut *received.lock().unwrap());
+    let divergences = compare_streams(recorded, &received, &config.ignored);
+
+    Ok(ReplayReport {
+        sent,
+        received,
+        divergences,
+        error,
+    })
+}
+
+/// Waits until the server sent every trigger at least as often as `required` says.
+async fn wait_for_triggers(
+    triggers_seen: &mut watch::Receiver<HashMap<&'static str, usize>>,
+    required: &HashMap<&'static str, usize>,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    // a trigger the server hasn't sent often enough yet, how often it did and has to
+    let missing = |seen: &HashMap<&'static str, usize>| {
+        required.iter().find_map(|(&name, &required)| {
+            let seen = seen.get(name).copied().unwrap_or(0);
+            (seen < required).then_some((name, seen, required))
+        })
+    };
+
+    let wait = async {
+        loop {
+            let Some((name, seen, required)) = missing(&triggers_seen.borrow_and_update()) else {
+                return Ok(());
+            };
+
+            if triggers_seen.changed().await.is_err() {
+                bail!(
+                    "server closed the connection while waiting for trigger {} ({} of {} seen)",
+                    name,
+                    seen,
+                    required
+                );
+            }
+        }
+    };
+
+    if let Ok(res) = tokio::time::timeout(timeout, wait).await {
+        return res;
+    }
+
+    match missing(&triggers_seen.borrow()) {
+        Some((name, seen, required)) => bail!(
+            "timed out waiting for trigger {} from the server ({} of {} seen)",
+            name,
+            seen,
+            required
+        ),
+        // it arrived just as the time ran out
+        None => Ok(()),
+    }
+}
+
+fn compare_streams(
+    recorded: &[Packet],
+    received: &[Packet],
+    ignored: &[String],
+) -> Vec<Divergence> {
+    let is_ignored = |p: &Packet| ignored.iter().any(|name| name == p.name);
+    let expected = recorded
+        .iter()
+        .filter(|p| p.side == PacketSide::Clientbound && !is_ignored(p))
+        .collect::<Vec<_>>();
+    let actual = received
+        .iter()
+        .filter(|p| !is_ignored(p))
+        .collect::<Vec<_>>();
+
+    let mut divergences = Vec::new();
+
+    let first_mismatch =
+        (0..expected.len().max(actual.len())).find(|&i| match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) => e.state != a.state || e.id != a.id,
+            _ => true,
+        });
+
+    if let Some(index) = first_mismatch {
+        divergences.push(Divergence::FirstMismatch {
+            index,
+            expected: expected.get(index).map(|p| p.name),
+            actual: actual.get(index).map(|p| p.name),
+        });
+    }
+
+    let mut counts: HashMap<(PacketState, i32), (&'static str, usize, usize)> = HashMap::new();
+    for p in &expected {
+        counts.entry((p.state, p.id)).or_insert((p.name, 0, 0)).1 += 1;
+    }
+    for p in &actual {
+        counts.entry((p.state, p.id)).or_insert((p.name, 0, 0)).2 += 1;
+    }
+
+    let mut count_mismatches = counts
+        .into_iter()
+        .filter(|(_, (_, expected, actual))| expected != actual)
+        .map(
+            |((state, _), (name, expected, actual))| Divergence::CountMismatch {
+                state,
+                name,
+                expected,
+                actual,
+            },
+        )
+        .collect::<Vec<_>>();
+    count_mismatches.sort_by_key(|d| match d {
+        Divergence::CountMismatch { name, .. } => *name,
+        Divergence::FirstMismatch { .. } => "",
+    });
+    divergences.extend(count_mismatches);
+
+    divergences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn packet(side: PacketSide, state: PacketState, id: i32, name: &'static str) -> Packet {
+        Packet {
+            side,
+            state,
+            id,
+            timestamp: None,
+            name,
+            data: None,
+            session: None,
+            schema: None,
+        }
+    }
+
+    fn s2c(id: i32, name: &'static str) -> Packet {
+        packet(PacketSide::Clientbound, PacketState::Play, id, name)
+    }
+
+    fn c2s(id: i32, name: &'static str) -> Packet {
+        packet(PacketSide::Serverbound, PacketState::Play, id, name)
+    }
+
+    fn ignored() -> Vec<String> {
+        vec!["KeepAliveS2c".to_string()]
+    }
+
+    #[test]
+    fn matching_streams_dont_diverge() {
+        let recorded = [
+            s2c(0x28, "GameJoinS2c"),
+            c2s(0x08, "ClientSettingsC2s"),
+            s2c(0x23, "KeepAliveS2c"),
+            s2c(0x3c, "PlayerPositionLookS2c"),
+        ];
+        let received = [s2c(0x28, "GameJoinS2c"), s2c(0x3c, "PlayerPositionLookS2c")];
+
+        assert_eq!(compare_streams(&recorded, &received, &ignored()), vec![]);
+    }
+
+    #[test]
+    fn diverging_streams_report_first_mismatch_and_counts() {
+        let recorded = [s2c(0x28, "GameJoinS2c"), s2c(0x3c, "PlayerPositionLookS2c")];
+        let received = [s2c(0x28, "GameJoinS2c"), s2c(0x1a, "DisconnectS2c")];
+
+        assert_eq!(
+            compare_streams(&recorded, &received, &ignored()),
+            vec![
+                Divergence::FirstMismatch {
+                    index: 1,
+                    expected: Some("PlayerPositionLookS2c"),
+                    actual: Some("DisconnectS2c"),
+                },
+                Divergence::CountMismatch {
+                    state: PacketState::Play,
+                    name: "DisconnectS2c",
+                    expected: 0,
+                    actual: 1,
+                },
+                Divergence::CountMismatch {
+                    state: PacketState::Play,
+                    name: "PlayerPositionLookS2c",
+                    expected: 1,
+                    actual: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_packets_are_reported() {
+        let recorded = [s2c(0x28, "GameJoinS2c"), s2c(0x3c, "PlayerPositionLookS2c")];
+        let received = [s2c(0x28, "GameJoinS2c")];
+
+        assert_eq!(
+            compare_streams(&recorded, &received, &ignored()),
+            vec![
+                Divergence::FirstMismatch {
+                    index: 1,
+                    expected: Some("PlayerPositionLookS2c"),
+                    actual: None,
+                },
+                Divergence::CountMismatch {
+                    state: PacketState::Play,
+                    name: "PlayerPositionLookS2c",
+                    expected: 1,
+                    actual: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn same_id_in_another_state_diverges() {
+        let recorded = [packet(
+            PacketSide::Clientbound,
+            PacketState::Login,
+            0x02,
+            "LoginSuccessS2c",
+        )];
+        let received = [s2c(0x02, "ChunkBiomeDataS2c")];
+
+        let divergences = compare_streams(&recorded, &received, &ignored());
+
+        assert_eq!(
+            divergences[0],
+            Divergence::FirstMismatch {
+                index: 0,
+                expected: Some("LoginSuccessS2c"),
+                actual: Some("ChunkBiomeDataS2c"),
+            }
+        );
+        assert_eq!(divergences.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn waits_until_triggers_were_seen_often_enough() {
+        let (sender, mut seen) = watch::channel(HashMap::new());
+        let required = HashMap::from([("GameJoinS2c", 2)]);
+
+        let server = tokio::spawn(async move {
+            for _ in 0..2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                sender.send_modify(|seen| *seen.entry("GameJoinS2c").or_insert(0) += 1);
+            }
+            // keep the connection open until the driver moved on
+            sender.closed().await;
+        });
+
+        wait_for_triggers(&mut seen, &required, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(seen.borrow()["GameJoinS2c"], 2);
+
+        drop(seen);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn nothing_required_returns_at_once() {
+        let (_sender, mut seen) = watch::channel(HashMap::new());
+
+        wait_for_triggers(&mut seen, &HashMap::new(), Duration::ZERO)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn times_out_on_missing_trigger() {
+        let (_sender, mut seen) = watch::channel(HashMap::new());
+        let required = HashMap::from([("LoginSuccessS2c", 1)]);
+
+        let err = wait_for_triggers(&mut seen, &required, Duration::from_millis(20))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "timed out waiting for trigger LoginSuccessS2c from the server (0 of 1 seen)"
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_when_server_closes_before_trigger() {
+        let (sender, mut seen) = watch::channel(HashMap::new());
+        let required = HashMap::from([("GameJoinS2c", 2)]);
+
+        sender.send_modify(|seen| *seen.entry("GameJoinS2c").or_insert(0) += 1);
+        drop(sender);
+
+        let err = wait_for_triggers(&mut seen, &required, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "server closed the connection while waiting for trigger GameJoinS2c (1 of 2 seen)"
+        );
+    }
+}
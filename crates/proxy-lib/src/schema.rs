@@ -0,0 +1,325 @@
+//! Field layouts for packets from definitions files, for packets valence has no type for. With
+//! the `decode` feature they are read at runtime into the same [`Field`] trees the built-in
+//! decoders produce.
+//!
+//! ```json
+//! "fields": [
+//!     { "name": "entity", "type": "VarInt" },
+//!     { "name": "target", "type": "Option", "of": { "type": "Position" } },
+//!     { "name": "entries", "type": "Array", "of": { "type": "Compound", "fields": [
+//!         { "name": "key", "type": "String" },
+//!         { "name": "data", "type": "Nbt" }
+//!     ] } }
+//! ]
+//! ```
+
+#[cfg(feature = "decode")]
+use crate::{decode::DecodeError, fields::Field};
+
+/// Layout of a packet body, read field by field.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(transparent))]
+pub struct PacketSchema {
+    pub fields: Vec<SchemaField>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+pub struct SchemaField {
+    pub name: String,
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub ty: SchemaType,
+}
+
+/// How a value is encoded, numbers are big endian like everywhere in the protocol.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize), serde(tag = "type"))]
+pub enum SchemaType {
+    Bool,
+    Byte,
+    UnsignedByte,
+    Short,
+    UnsignedShort,
+    Int,
+    Long,
+    Float,
+    Double,
+    VarInt,
+    VarLong,
+    /// VarInt length prefixed UTF-8
+    String,
+    Uuid,
+    /// Block position packed into a long
+    Position,
+    /// Network NBT, a lone end tag stands for no compound
+    Nbt,
+    /// VarInt length prefixed bytes
+    ByteArray,
+    /// Whatever is left of the body
+    Rest,
+    /// Bool telling whether the value follows
+    Option {
+        of: Box<SchemaType>,
+    },
+    /// VarInt count followed by that many values
+    Array {
+        of: Box<SchemaType>,
+    },
+    Compound {
+        fields: Vec<SchemaField>,
+    },
+}
+
+impl SchemaType {
+    /// The name of the type in definitions files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bool => "Bool",
+            Self::Byte => "Byte",
+            Self::UnsignedByte => "UnsignedByte",
+            Self::Short => "Short",
+            Self::UnsignedShort => "UnsignedShort",
+            Self::Int => "Int",
+            Self::Long => "Long",
+            Self::Float => "Float",
+            Self::Double => "Double",
+            Self::VarInt => "VarInt",
+            Self::VarLong => "VarLong",
+            Self::String => "String",
+            Self::Uuid => "Uuid",
+            Self::Position => "Position",
+            Self::Nbt => "Nbt",
+            Self::ByteArray => "ByteArray",
+            Self::Rest => "Rest",
+            Self::Option { .. } => "Option",
+            Self::Array { .. } => "Array",
+            Self::Compound { .. } => "Compound",
+        }
+    }
+}
+
+#[cfg(feature = "decode")]
+impl PacketSchema {
+    /// Reads `body` into a tree rooted at a node named after the packet, returning it with the
+    /// number of bytes read.
+    pub(crate) fn decode(
+        &self,
+        packet_name: &str,
+        body: &[u8],
+    ) -> Result<(Field, usize), DecodeError> {
+        let mut reader = Reader { body, pos: 0 };
+
+        let children = self
+            .fields
+            .iter()
+            .map(|field| reader.field(field.name.clone(), &field.ty))
+            .collect::<anyhow::Result<Vec<_>>>()
+            .map_err(|e| DecodeError {
+                message: format!("{:#}", e),
+                offset: reader.pos,
+                remaining: bytes::Bytes::copy_from_slice(&body[reader.pos..]),
+            })?;
+
+        let mut root = Field::node(String::new(), packet_name, children);
+        root.offset = Some(0);
+        root.len = Some(reader.pos);
+
+        Ok((root, reader.pos))
+    }
+}
+
+#[cfg(feature = "decode")]
+struct Reader<'a> {
+    body: &'a [u8],
+    pos: usize,
+}
+
+#[cfg(feature = "decode")]
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let left = self.body.len() - self.pos;
+        anyhow::ensure!(
+            len <= left,
+            "unexpected end of packet, {} bytes needed but {} left",
+            len,
+            left
+        );
+
+        let bytes = &self.body[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn var_int(&mut self, max_len: usize) -> anyhow::Result<u64> {
+        match crate::fields::read_var_int(&self.body[self.pos..]) {
+            Some((raw, len)) if len <= max_len => {
+                self.pos += len;
+                Ok(raw)
+            }
+            Some(_) => anyhow::bail!("VarInt is too long"),
+            None => anyhow::bail!("unexpected end of packet in a VarInt"),
+        }
+    }
+
+    /// A VarInt length or count, which has to fit in what is left of the body.
+    fn len(&mut self) -> anyhow::Result<usize> {
+        let len = self.var_int(5)? as u32 as i32;
+        anyhow::ensure!(len >= 0, "negative length {}", len);
+        anyhow::ensure!(
+            len as usize <= self.body.len() - self.pos,
+            "length {} is longer than the rest of the packet",
+            len
+        );
+        Ok(len as usize)
+    }
+
+    fn field(&mut self, name: String, ty: &SchemaType) -> anyhow::Result<Field> {
+        let start = self.pos;
+        let leaf = |ty: &str, value: String| Field::leaf(name.clone(), ty, value);
+
+        let mut field = match ty {
+            SchemaType::Bool => leaf("bool", (self.take(1)?[0] != 0).to_string()),
+            SchemaType::Byte => leaf("i8", i8::from_be_bytes(self.array()?).to_string()),
+            SchemaType::UnsignedByte => leaf("u8", self.take(1)?[0].to_string()),
+            SchemaType::Short => leaf("i16", i16::from_be_bytes(self.array()?).to_string()),
+            SchemaType::UnsignedShort => leaf("u16", u16::from_be_bytes(self.array()?).to_string()),
+            SchemaType::Int => leaf("i32", i32::from_be_bytes(self.array()?).to_string()),
+            SchemaType::Long => leaf("i64", i64::from_be_bytes(self.array()?).to_string()),
+            SchemaType::Float => leaf("f32", format!("{:?}", f32::from_be_bytes(self.array()?))),
+            SchemaType::Double => leaf("f64", format!("{:?}", f64::from_be_bytes(self.array()?))),
+            SchemaType::VarInt => leaf("VarInt", (self.var_int(5)? as u32 as i32).to_string()),
+            SchemaType::VarLong => leaf("VarLong", (self.var_int(10)? as i64).to_string()),
+            SchemaType::String => {
+                let len = self.len()?;
+                let s = std::str::from_utf8(self.take(len)?)?;
+                leaf("str", s.to_string())
+            }
+            SchemaType::Uuid => {
+                let hex = format!("{:032x}", u128::from_be_bytes(self.array()?));
+                leaf(
+                    "uuid",
+                    format!(
+                        "{}-{}-{}-{}-{}",
+                        &hex[..8],
+                        &hex[8..12],
+                        &hex[12..16],
+                        &hex[16..20],
+                        &hex[20..]
+                    ),
+                )
+            }
+            SchemaType::Position => {
+                let packed = i64::from_be_bytes(self.array()?);
+                let coords = [
+                    ("x", packed >> 38),
+                    ("y", packed << 52 >> 52),
+                    ("z", packed << 26 >> 38),
+                ];
+
+                Field::node(
+                    name.clone(),
+                    "BlockPos",
+                    coords
+                        .into_iter()
+                        .map(|(axis, v)| Field::leaf(axis.to_string(), "int", v.to_string()))
+                        .collect(),
+                )
+            }
+            SchemaType::Nbt => {
+                if self.body.get(self.pos) == Some(&0) {
+                    self.pos += 1;
+                    leaf("Option", "None".to_string())
+                } else {
+                    use valence::protocol::Decode;
+
+                    let mut r = &self.body[self.pos..];
+                    let compound = valence::nbt::Compound::decode(&mut r)?;
+                    self.pos = self.body.len() - r.len();
+
+                    leaf("Compound", format!("{:?}", compound))
+                }
+            }
+            SchemaType::ByteArray => {
+                let len = self.len()?;
+                leaf("bytes", hex(self.take(len)?))
+            }
+            SchemaType::Rest => leaf("bytes", hex(self.take(self.body.len() - self.pos)?)),
+            SchemaType::Option { of } => {
+                if self.take(1)?[0] != 0 {
+                    Field::node(name.clone(), "Some", vec![self.field("0".to_string(), of)?])
+                } else {
+                    leaf("Option", "None".to_string())
+                }
+            }
+            SchemaType::Array { of } => {
+                let count = self.len()?;
+                let items = (0..count)
+                    .map(|i| self.field(format!("[{}]", i), of))
+                    .collect::<anyhow::Result<_>>()?;
+                Field::node(name.clone(), "list", items)
+            }
+            SchemaType::Compound { fields } => {
+                let children = fields
+                    .iter()
+                    .map(|field| self.field(field.name.clone(), &field.ty))
+                    .collect::<anyhow::Result<_>>()?;
+                Field::node(name.clone(), "struct", children)
+            }
+        };
+
+        field.offset = Some(start);
+        field.len = Some(self.pos - start);
+        Ok(field)
+    }
+}
+
+#[cfg(feature = "decode")]
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Pretty prints a field tree the way `{:#?}` would print the packet.
+#[cfg(feature = "decode")]
+pub(crate) fn to_debug(field: &Field) -> String {
+    let mut out = String::new();
+    write_debug(field, 0, &mut out);
+    out
+}
+
+#[cfg(feature = "decode")]
+fn write_debug(field: &Field, depth: usize, out: &mut String) {
+    if field.children.is_empty() {
+        match field.ty.as_str() {
+            "str" => out.push_str(&format!("{:?}", field.value)),
+            "bytes" => out.push_str(&format!("b\"{}\"", field.value)),
+            _ => out.push_str(&field.value),
+        }
+        return;
+    }
+
+    let (open, close, named) = match field.ty.as_str() {
+        "list" => ("[".to_string(), ']', false),
+        "Some" => ("Some(".to_string(), ')', false),
+        "map" => ("{".to_string(), '}', true),
+        ty => (format!("{} {{", ty), '}', true),
+    };
+
+    let indent = "    ".repeat(depth + 1);
+    out.push_str(&open);
+    out.push('\n');
+    for child in &field.children {
+        out.push_str(&indent);
+        if named {
+            out.push_str(&child.name);
+            out.push_str(": ");
+        }
+        write_debug(child, depth + 1, out);
+        out.push_str(",\n");
+    }
+    out.push_str(&"    ".repeat(depth));
+    out.push(close);
+}
@@ -0,0 +1,69 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{Arc, OnceLock},
+};
+
+use time::OffsetDateTime;
+
+use crate::{connection_state::ConnectionState, packet_registry::PacketState};
+
+/// Identifies a single client connection accepted by the proxy.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct SessionId(pub u64);
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub id: SessionId,
+    /// Peer address of the connecting client
+    pub client_addr: SocketAddr,
+    /// Backend the client is being relayed to
+    pub server_addr: SocketAddr,
+    pub accepted_at: OffsetDateTime,
+    /// Set once the client's handshake is seen
+    pub(crate) protocol_version: OnceLock<i32>,
+    /// Advanced by the proxy as it relays the connection's packets
+    pub(crate) connection: Arc<ConnectionState>,
+}
+
+impl Session {
+    /// Protocol version the client announced in its handshake, `None` before the handshake.
+    pub fn protocol_version(&self) -> Option<i32> {
+        self.protocol_version.get().copied()
+    }
+
+    /// The state the connection is in now. Sessions read from a capture stay in
+    /// [`PacketState::Handshaking`].
+    pub fn state(&self) -> PacketState {
+        self.connection.state()
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CloseReason {
+    ClientDisconnected,
+    ServerDisconnected,
+    ServerUnreachable(String),
+    /// Closed by a forced shutdown of the proxy
+    Shutdown,
+    Error(String),
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloseReason::ClientDisconnected => write!(f, "client disconnected"),
+            CloseReason::ServerDisconnected => write!(f, "server disconnected"),
+            CloseReason::ServerUnreachable(e) => write!(f, "server unreachable: {e}"),
+            CloseReason::Shutdown => write!(f, "proxy shut down"),
+            CloseReason::Error(e) => write!(f, "error: {e}"),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ShutdownMode {
+    /// Stop accepting and wait for the live sessions to disconnect on their own
+    Drain,
+    /// Stop accepting and close every live session right away
+    Force,
+}
+
+/// Stops a running [`crate::Proxy`] from another task.
+///
+/// A drain can be escalated by calling [`ShutdownHandle::shutdown`] again with
+/// [`ShutdownMode::Force`].
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    sender: Arc<watch::Sender<Option<ShutdownMode>>>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(sender: Arc<watch::Sender<Option<ShutdownMode>>>) -> Self {
+        Self { sender }
+    }
+
+    pub fn shutdown(&self, mode: ShutdownMode) {
+        self.sender.send_replace(Some(mode));
+    }
+}
+
+/// What [`crate::Proxy::run`] did before it returned.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RunSummary {
+    pub sessions_accepted: u64,
+    /// Sessions that disconnected on their own after the shutdown was requested
+    pub sessions_drained: u64,
+    /// Sessions closed by a forced shutdown
+    pub sessions_closed: u64,
+}
+
+pub(crate) async fn wait_for_shutdown(
+    receiver: &mut watch::Receiver<Option<ShutdownMode>>,
+) -> ShutdownMode {
+    loop {
+        let mode = *receiver.borrow_and_update();
+        if let Some(mode) = mode {
+            return mode;
+        }
+
+        if receiver.changed().await.is_err() {
+            // the proxy is gone, nobody can ask us to stop anymore
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+pub(crate) async fn wait_for_force(receiver: &mut watch::Receiver<Option<ShutdownMode>>) {
+    while wait_for_shutdown(receiver).await != ShutdownMode::Force {
+        if receiver.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}